@@ -0,0 +1,52 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use query_graph::ConcurrentMap;
+
+const ENTRIES: usize = 10_000;
+
+/// A query that's expensive to clone, like one holding a path and a few
+/// strings.
+fn keys() -> Vec<Vec<String>> {
+    (0..ENTRIES)
+        .map(|i| {
+            (0..8)
+                .map(|part| format!("src/module_{}/file_{}.rs", i, part))
+                .collect()
+        })
+        .collect()
+}
+
+/// Gets every (already inserted) key, the common case when the graph looks up
+/// the node of a query. `get_or_insert` takes the key by value, so it has to be
+/// cloned on every lookup, while `get_or_insert_with_key` only clones it when
+/// it's inserted.
+fn get_existing(c: &mut Criterion) {
+    let keys = keys();
+    let map = ConcurrentMap::new();
+
+    for (i, key) in keys.iter().enumerate() {
+        map.get_or_insert(key.clone(), || i);
+    }
+
+    let mut group = c.benchmark_group("get 10k existing clone-heavy keys");
+
+    group.bench_function("get_or_insert", |b| {
+        b.iter(|| {
+            for key in &keys {
+                black_box(map.get_or_insert(key.clone(), || unreachable!()));
+            }
+        })
+    });
+
+    group.bench_function("get_or_insert_with_key", |b| {
+        b.iter(|| {
+            for key in &keys {
+                black_box(map.get_or_insert_with_key(key, |_| unreachable!()));
+            }
+        })
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, get_existing);
+criterion_main!(benches);
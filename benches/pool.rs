@@ -0,0 +1,85 @@
+use std::sync::Arc;
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use query_graph::{Graph, GraphBuilder, QueryResolver, ResolveQuery};
+
+const NODES: u32 = 250;
+const FAN_OUT: u32 = 8;
+const INPUTS: u32 = 64;
+const INCREMENTS: u32 = 100;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Query {
+    Root,
+    Node(u32),
+    Input(u32),
+}
+
+/// Resolves the graph as it is after `iteration` increments, each of which
+/// changed one input (in turn).
+struct Resolver {
+    iteration: u32,
+}
+
+impl ResolveQuery<Query, u32> for Resolver {
+    fn resolve(&self, q: Query, resolve: Arc<QueryResolver<Query, u32>>) -> u32 {
+        match q {
+            Query::Root => (0..NODES).map(|n| resolve.query(Query::Node(n))).sum(),
+            Query::Node(n) => (0..FAN_OUT)
+                .map(|i| resolve.query(Query::Input((n * FAN_OUT + i) % INPUTS)))
+                .sum(),
+            Query::Input(i) => self.iteration / INPUTS + u32::from(i < self.iteration % INPUTS),
+        }
+    }
+
+    fn is_input(&self, q: &Query) -> bool {
+        matches!(q, Query::Input(_))
+    }
+}
+
+/// Makes many small increments (like one per keystroke), each changing a
+/// single input so that a few nodes are resolved again.
+fn increment(graph: Arc<Graph<Query, u32>>) -> Arc<Graph<Query, u32>> {
+    let mut graph = graph;
+    graph.query(Query::Root);
+
+    for iteration in 0..INCREMENTS {
+        graph.invalidate(Query::Input(iteration % INPUTS));
+        graph = graph.increment(Resolver {
+            iteration: iteration + 1,
+        });
+        graph.query(Query::Root);
+    }
+
+    graph
+}
+
+/// Increments a graph 100 times without and with pooled edge sets.
+fn small_increments(c: &mut Criterion) {
+    let mut group = c.benchmark_group("100 small increments");
+
+    group.bench_function("unpooled", |b| {
+        b.iter_batched(
+            || GraphBuilder::new().build(Resolver { iteration: 0 }),
+            increment,
+            BatchSize::SmallInput,
+        )
+    });
+
+    group.bench_function("pool_edge_sets", |b| {
+        b.iter_batched(
+            || {
+                GraphBuilder::new()
+                    .pool_edge_sets(1024)
+                    .build(Resolver { iteration: 0 })
+            },
+            increment,
+            BatchSize::SmallInput,
+        )
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, small_increments);
+criterion_main!(benches);
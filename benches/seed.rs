@@ -0,0 +1,48 @@
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use query_graph::ConcurrentMap;
+
+const ENTRIES: u64 = 100_000;
+
+fn entries() -> Vec<(u64, u64)> {
+    (0..ENTRIES).map(|key| (key, key * 2)).collect()
+}
+
+/// Seeds an empty map with 100k entries, one `get_or_insert` at a time (which
+/// locks a shard per entry) and in a single batched `extend` (which locks each
+/// shard once).
+fn seed(c: &mut Criterion) {
+    let mut group = c.benchmark_group("seed 100k entries");
+
+    group.bench_function("get_or_insert", |b| {
+        b.iter_batched(
+            entries,
+            |entries| {
+                let map = ConcurrentMap::new();
+
+                for (key, value) in entries {
+                    map.get_or_insert(key, || value);
+                }
+
+                map
+            },
+            BatchSize::LargeInput,
+        )
+    });
+
+    group.bench_function("extend", |b| {
+        b.iter_batched(
+            entries,
+            |entries| {
+                let map = ConcurrentMap::new();
+                map.extend(entries);
+                map
+            },
+            BatchSize::LargeInput,
+        )
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, seed);
+criterion_main!(benches);
@@ -0,0 +1,72 @@
+use std::sync::Arc;
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use query_graph::{Graph, QueryResolver, ResolveQuery};
+
+const QUERIES: usize = 10_000;
+const INCREMENTS: usize = 1_000;
+
+/// The "whole program" query of a compiler, cheap to resolve so that the
+/// benchmark measures the graph.
+struct Resolver;
+
+impl ResolveQuery<(), u64> for Resolver {
+    fn resolve(&self, _q: (), _resolve: Arc<QueryResolver<(), u64>>) -> u64 {
+        42
+    }
+}
+
+type NewGraph = fn() -> Arc<Graph<(), u64>>;
+
+/// The graphs to compare: the default `ConcurrentMap` and the singleton.
+fn graphs() -> [(&'static str, NewGraph); 2] {
+    [
+        ("concurrent_map", || Graph::new(Resolver)),
+        ("singleton", || Graph::singleton(Resolver)),
+    ]
+}
+
+/// Queries the (already resolved) unit query over and over, the common case of
+/// a singleton.
+fn query_resolved(c: &mut Criterion) {
+    let mut group = c.benchmark_group("query a resolved unit query 10k times");
+
+    for (name, graph) in graphs() {
+        let graph = graph();
+        graph.query(());
+
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                for _ in 0..QUERIES {
+                    black_box(graph.query(()));
+                }
+            })
+        });
+    }
+
+    group.finish();
+}
+
+/// Increments the graph and queries the unit query in every iteration, which
+/// resolves it again in a new map each time.
+fn increment(c: &mut Criterion) {
+    let mut group = c.benchmark_group("increment and query 1k times");
+
+    for (name, graph) in graphs() {
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                let mut graph = graph();
+
+                for _ in 0..INCREMENTS {
+                    black_box(graph.query(()));
+                    graph = graph.increment(Resolver);
+                }
+            })
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, query_resolved, increment);
+criterion_main!(benches);
@@ -0,0 +1,123 @@
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{parse_macro_input, parse_quote, Data, DeriveInput, Fields, Index, Type};
+
+/// Derives `Clone`, `PartialEq`, `Eq`, `Hash`, and `query_graph::Query` for a
+/// struct, so it can be used as a query key. The output type is declared with
+/// the `query` attribute:
+///
+/// ```ignore
+/// #[derive(Query)]
+/// #[query(output = TypeCheckResult)]
+/// struct TypeCheck {
+///     file: PathBuf,
+///     scope: Scope,
+/// }
+/// ```
+///
+/// Every field is cloned, compared, and hashed in declaration order.
+#[proc_macro_derive(Query, attributes(query))]
+pub fn derive_query(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+
+    match expand(input) {
+        Ok(tokens) => tokens.into(),
+        Err(error) => error.to_compile_error().into(),
+    }
+}
+
+fn expand(mut input: DeriveInput) -> syn::Result<TokenStream2> {
+    let output = output_type(&input)?;
+
+    let Data::Struct(data) = &input.data else {
+        return Err(syn::Error::new_spanned(
+            &input.ident,
+            "`Query` can only be derived for structs (implement it manually for enums)",
+        ));
+    };
+
+    let members = data
+        .fields
+        .iter()
+        .enumerate()
+        .map(|(i, field)| match &field.ident {
+            Some(ident) => quote!(#ident),
+            None => {
+                let index = Index::from(i);
+                quote!(#index)
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let clone = match &data.fields {
+        Fields::Named(_) => {
+            quote!(Self { #(#members: ::core::clone::Clone::clone(&self.#members)),* })
+        }
+        Fields::Unnamed(_) => quote!(Self(#(::core::clone::Clone::clone(&self.#members)),*)),
+        Fields::Unit => quote!(Self),
+    };
+
+    // Like the std derives, every type parameter is bounded by the derived traits.
+    for param in input.generics.type_params_mut() {
+        param.bounds.push(parse_quote!(::core::clone::Clone));
+        param.bounds.push(parse_quote!(::core::cmp::Eq));
+        param.bounds.push(parse_quote!(::core::hash::Hash));
+    }
+
+    let ident = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    Ok(quote! {
+        impl #impl_generics ::core::clone::Clone for #ident #ty_generics #where_clause {
+            fn clone(&self) -> Self {
+                #clone
+            }
+        }
+
+        impl #impl_generics ::core::cmp::PartialEq for #ident #ty_generics #where_clause {
+            fn eq(&self, other: &Self) -> bool {
+                true #(&& self.#members == other.#members)*
+            }
+        }
+
+        impl #impl_generics ::core::cmp::Eq for #ident #ty_generics #where_clause {}
+
+        impl #impl_generics ::core::hash::Hash for #ident #ty_generics #where_clause {
+            fn hash<H: ::core::hash::Hasher>(&self, state: &mut H) {
+                #(::core::hash::Hash::hash(&self.#members, state);)*
+            }
+        }
+
+        impl #impl_generics ::query_graph::Query for #ident #ty_generics #where_clause {
+            type Output = #output;
+        }
+    })
+}
+
+/// Parses the output type from the `#[query(output = T)]` attribute.
+fn output_type(input: &DeriveInput) -> syn::Result<Type> {
+    let mut output = None;
+
+    for attr in input
+        .attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("query"))
+    {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("output") {
+                output = Some(meta.value()?.parse()?);
+                Ok(())
+            } else {
+                Err(meta.error("expected `output`"))
+            }
+        })?;
+    }
+
+    output.ok_or_else(|| {
+        syn::Error::new_spanned(
+            &input.ident,
+            "missing the output type, add `#[query(output = T)]`",
+        )
+    })
+}
@@ -5,11 +5,12 @@ use std::{
 };
 
 use enum_as_inner::EnumAsInner;
-use query_graph::{Graph, QueryResolver, ResolveQuery};
+use query_graph::{Graph, QueryResolver, QueryResultExt, ResolveQuery};
 use rayon::prelude::{IntoParallelRefIterator, ParallelIterator};
 
 #[derive(Clone)]
 struct Document {
+    #[allow(dead_code)]
     path: PathBuf,
     content: String,
 }
@@ -24,6 +25,7 @@ struct SemanticModel {
     syntax_trees: HashMap<PathBuf, Arc<SyntaxTree>>,
 }
 
+#[allow(clippy::enum_variant_names)]
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 enum Query {
     GetAllDocuments,
@@ -32,6 +34,7 @@ enum Query {
     GetSemanticModel,
 }
 
+#[allow(clippy::enum_variant_names)]
 #[derive(Debug, Clone, PartialEq, Eq, EnumAsInner)]
 enum QueryResult {
     GetAllDocuments(HashSet<PathBuf>),
@@ -79,7 +82,9 @@ impl Snapshot {
 
     fn get_semantic_model(&self) -> Arc<SemanticModel> {
         let result = self.graph.query(Query::GetSemanticModel);
-        result.as_get_semantic_model().unwrap().clone()
+        result
+            .expect_variant(QueryResult::as_get_semantic_model)
+            .clone()
     }
 
     fn increment(&self, new_state: Arc<CompilerState>) -> Snapshot {
@@ -107,20 +112,24 @@ impl ResolveQuery<Query, QueryResult> for Arc<CompilerState> {
             }),
             Query::GetSyntaxTree(path) => QueryResult::GetSyntaxTree({
                 let content = resolver.query(Query::GetDocumentContent(path));
-                let content = content.as_get_document_content().unwrap().clone();
+                let content = content
+                    .expect_variant(QueryResult::as_get_document_content)
+                    .clone();
 
                 Arc::new(SyntaxTree { content })
             }),
             Query::GetSemanticModel => QueryResult::GetSemanticModel({
                 let documents = resolver.query(Query::GetAllDocuments);
-                let documents = documents.as_get_all_documents().unwrap();
+                let documents = documents.expect_variant(QueryResult::as_get_all_documents);
 
                 Arc::new(SemanticModel {
                     syntax_trees: documents
                         .par_iter()
                         .map(|path| {
                             let tree = resolver.query(Query::GetSyntaxTree(path.clone()));
-                            (path.clone(), tree.as_get_syntax_tree().unwrap().clone())
+                            let tree = tree.expect_variant(QueryResult::as_get_syntax_tree);
+
+                            (path.clone(), tree.clone())
                         })
                         .collect::<HashMap<_, _>>(),
                 })
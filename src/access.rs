@@ -0,0 +1,40 @@
+use std::{
+    hash::Hash,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+
+use crate::{Graph, Slot};
+
+/// How often and when a node was last accessed.
+#[derive(Debug, Default)]
+pub(crate) struct AccessCounters {
+    count: AtomicU64,
+    /// The nanoseconds since the graph was created.
+    last: AtomicU64,
+}
+
+impl<Q: Clone + Eq + Hash + Send + Sync, R: Send + Sync> Graph<Q, R> {
+    /// Returns the last time and number of times each node in this iteration
+    /// was accessed (queried, including by other queries), for finding hot and
+    /// cold queries. Nodes that were never accessed in this iteration (like
+    /// installed or restored ones) are skipped.
+    pub fn access_stats(&self) -> Vec<(Q, Instant, u64)> {
+        self.new
+            .iter()
+            .filter_map(|(q, slot)| {
+                let count = slot.access.count.load(Ordering::Relaxed);
+                let last = slot.access.last.load(Ordering::Relaxed);
+
+                (count > 0).then(|| (q, self.created + Duration::from_nanos(last), count))
+            })
+            .collect()
+    }
+
+    pub(crate) fn record_access(&self, slot: &Slot<Q, R>) {
+        let now = self.created.elapsed().as_nanos() as u64;
+
+        slot.access.count.fetch_add(1, Ordering::Relaxed);
+        slot.access.last.fetch_max(now, Ordering::Relaxed);
+    }
+}
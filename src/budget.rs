@@ -0,0 +1,257 @@
+use std::{
+    hash::Hash,
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use rayon::prelude::{IntoParallelIterator, ParallelIterator};
+
+use crate::{cycle::Path, Graph, Shared};
+
+/// The payload a resolution unwinds with when the query it was resolved for
+/// exceeds its budget (see `Graph::query_with_budget`). Like [`Cancelled`](crate::Cancelled)
+/// it's raised with `resume_unwind`, so it doesn't run the panic hook.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BudgetExceeded;
+
+/// The number of resolutions a top-level query may make (see
+/// `Graph::query_with_budget`), or the deadline it has to make them by (see
+/// `Graph::query_all_with_deadline`).
+pub(crate) struct Budget {
+    max_resolutions: usize,
+    resolutions: AtomicUsize,
+    deadline: Option<Instant>,
+}
+
+impl Budget {
+    pub(crate) fn new(max_resolutions: usize) -> Self {
+        Self {
+            max_resolutions,
+            resolutions: AtomicUsize::new(0),
+            deadline: None,
+        }
+    }
+
+    pub(crate) fn until(deadline: Instant) -> Self {
+        Self {
+            deadline: Some(deadline),
+            ..Self::new(usize::MAX)
+        }
+    }
+
+    /// Unwinds with [`BudgetExceeded`] if the budget was already exceeded (by
+    /// another resolution of the same query), or the deadline passed.
+    pub(crate) fn check(&self) {
+        if self.resolutions.load(Ordering::Relaxed) > self.max_resolutions
+            || self
+                .deadline
+                .is_some_and(|deadline| Instant::now() >= deadline)
+        {
+            panic::resume_unwind(Box::new(BudgetExceeded));
+        }
+    }
+
+    /// Spends a resolution, unwinding with [`BudgetExceeded`] if that exceeds
+    /// the budget. The count saturates instead of wrapping, so an exceeded
+    /// budget can never look unspent again.
+    pub(crate) fn spend(&self) {
+        let spent = self
+            .resolutions
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |spent| {
+                Some(spent.saturating_add(1))
+            })
+            .unwrap();
+
+        if spent >= self.max_resolutions {
+            panic::resume_unwind(Box::new(BudgetExceeded));
+        }
+    }
+}
+
+impl<Q: Clone + Eq + Hash + Send + Sync, R: Clone + Send + Sync> Graph<Q, R> {
+    /// Like `query`, but gives up with [`BudgetExceeded`] once resolving the
+    /// query (and its dependencies) takes more than `max_resolutions` calls to
+    /// the resolver. Reused results are free, so this bounds the latency of a
+    /// query that would otherwise trigger a lot of work, letting the caller
+    /// fall back to a stale result instead.
+    ///
+    /// The budget is checked cooperatively whenever a query is resolved, so a
+    /// single resolution still runs to completion. The query is resolved in a
+    /// scratch layer on top of this iteration, which is only merged into it if
+    /// the query finishes within the budget, so an exceeded budget doesn't
+    /// cache any of the work it did.
+    pub fn query_with_budget(
+        self: &Arc<Self>,
+        q: Q,
+        max_resolutions: usize,
+    ) -> Result<R, BudgetExceeded> {
+        self.log_query(&q);
+
+        let scratch = self.scratch_layer();
+        let result = scratch.query_within(q, Budget::new(max_resolutions))?;
+        self.merge(&scratch);
+
+        Ok(result)
+    }
+
+    /// Resolves as many of the queries (in parallel) as finish before the
+    /// timeout elapses, and returns their results in the same order, with
+    /// `None` for the rest (e.g. to compute whatever fits in a frame of an
+    /// interactive app). Queries that are already resolved are returned even
+    /// after the deadline.
+    ///
+    /// Like a budget, the deadline is checked cooperatively whenever a query
+    /// is resolved, so a single resolution still runs to completion (and can
+    /// overrun the deadline). Everything resolved before the deadline is
+    /// cached, so querying the rest again later picks up where this left off.
+    pub fn query_all_with_deadline(
+        self: &Arc<Self>,
+        queries: impl IntoIterator<Item = Q>,
+        timeout: Duration,
+    ) -> Vec<Option<R>> {
+        let deadline = Instant::now() + timeout;
+        let queries = queries.into_iter().collect::<Vec<_>>();
+
+        queries.iter().for_each(|q| self.log_query(q));
+
+        queries
+            .into_par_iter()
+            .map(|q| self.query_within(q, Budget::until(deadline)).ok())
+            .collect()
+    }
+
+    /// Creates a scratch layer on top of this iteration, which reuses the nodes
+    /// resolved in it and resolves everything else like it would've (against
+    /// the same old map and invalidations), but into its own new map.
+    fn scratch_layer(&self) -> Arc<Self> {
+        let mut scratch = Self::create(
+            self.old.clone(),
+            self.old_namespaces.clone(),
+            self.resolver.clone(),
+            self.config.clone(),
+            Shared {
+                log: None,
+                // The scratch layer isn't part of this graph's lineage, so
+                // other graphs never validate against it.
+                lineage: Default::default(),
+                // Its results are only sent to the subscribers once they're
+                // merged into this iteration.
+                subscribers: Default::default(),
+                ..self.shared()
+            },
+        );
+
+        Arc::get_mut(&mut scratch)
+            .expect("the scratch layer isn't shared yet")
+            .base = Some(self.new.clone());
+
+        self.carry_invalidations(&scratch);
+        scratch
+    }
+
+    /// Moves the nodes resolved in the scratch layer into this iteration,
+    /// except for those that were resolved (or started resolving) in it
+    /// meanwhile.
+    fn merge(&self, scratch: &Self) {
+        for (q, slot) in scratch.new.iter() {
+            let Some(node) = slot.get() else {
+                continue;
+            };
+
+            let merged = self.new.get_or_insert_with_key(&q, |_| slot.clone());
+
+            if Arc::ptr_eq(&merged, &slot) {
+                self.record_namespace(&q);
+                self.stats.record(node.resolution);
+                self.notify_changed(&q, node);
+            }
+        }
+
+        self.generation.fetch_add(1, Ordering::Release);
+    }
+
+    fn query_within(self: &Arc<Self>, q: Q, budget: Budget) -> Result<R, BudgetExceeded> {
+        let path = Path::with_budget(budget);
+        let result = panic::catch_unwind(AssertUnwindSafe(|| self.query_on(q, &path)));
+
+        match result {
+            Ok(result) => Ok(result.as_ref().clone()),
+            Err(payload) => match payload.downcast::<BudgetExceeded>() {
+                Ok(exceeded) => Err(*exceeded),
+                Err(payload) => panic::resume_unwind(payload),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{QueryResolver, ResolveQuery};
+
+    use super::*;
+
+    fn exceeds(f: impl FnOnce()) -> bool {
+        match panic::catch_unwind(AssertUnwindSafe(f)) {
+            Ok(()) => false,
+            Err(payload) => payload.downcast::<BudgetExceeded>().is_ok(),
+        }
+    }
+
+    #[test]
+    fn exceeded_budgets_dont_wrap() {
+        // A budget whose count is about to overflow.
+        let budget = Budget {
+            resolutions: AtomicUsize::new(usize::MAX - 2),
+            ..Budget::new(usize::MAX - 2)
+        };
+
+        // Every spend past the limit is exceeded, even once the count would've
+        // wrapped back to zero.
+        for _ in 0..4 {
+            assert!(exceeds(|| budget.spend()));
+            assert!(exceeds(|| budget.check()));
+        }
+
+        assert_eq!(budget.resolutions.load(Ordering::Relaxed), usize::MAX);
+    }
+
+    /// `n` depends on every query below it.
+    struct Resolver;
+
+    impl ResolveQuery<u32, u32> for Resolver {
+        fn resolve(&self, q: u32, resolve: Arc<QueryResolver<u32, u32>>) -> u32 {
+            (0..q).map(|q| resolve.query(q)).sum::<u32>() + 1
+        }
+    }
+
+    #[test]
+    fn queries_within_budget() {
+        let graph = Graph::new(Resolver);
+
+        // Resolving 10 takes 11 resolutions (0 to 10), and nothing resolved
+        // within the budget is cached.
+        assert_eq!(graph.query_with_budget(10, 5), Err(BudgetExceeded));
+        assert_eq!(graph.query_with_budget(10, 6), Err(BudgetExceeded));
+        assert_eq!(graph.new.len(), 0);
+
+        // Once the query finishes within the budget everything it resolved is
+        // cached, so it's free from then on.
+        assert_eq!(graph.query_with_budget(10, 11), Ok(1024));
+        assert_eq!(graph.new.len(), 11);
+        assert_eq!(graph.query_with_budget(10, 0), Ok(1024));
+        assert_eq!(graph.query_with_budget(4, 0), Ok(16));
+    }
+
+    #[test]
+    fn budgets_reuse_resolved_queries() {
+        let graph = Graph::new(Resolver);
+        assert_eq!(graph.query(8), 256);
+
+        // Only 9 and 10 are resolved.
+        assert_eq!(graph.query_with_budget(10, 2), Ok(1024));
+    }
+}
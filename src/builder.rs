@@ -0,0 +1,398 @@
+use std::{
+    fmt::Debug,
+    hash::Hash,
+    sync::{Arc, OnceLock},
+    time::Duration,
+};
+
+use parking_lot::Mutex;
+
+use crate::{
+    change::{ChangeDetector, Equality},
+    compress::Compressor,
+    cycle::{self, CycleThrower},
+    map::{ConcurrentMap, NodeStore, NodeStoreFamily},
+    pool::EdgePool,
+    progress::ProgressSink,
+    redact::Redaction,
+    stats::Resolution,
+    store::NodeMap,
+    verify::Verifier,
+    Graph, NodeRef, QueryLog, ResolveQuery, Shared, Snapshot,
+};
+
+#[cfg(feature = "debug")]
+use crate::slow_clone::SlowClones;
+
+/// A `GraphBuilder` is used to configure a `Graph` before it's created. The
+/// configuration is carried over to every iteration incremented from it.
+pub struct GraphBuilder<Q, R> {
+    config: Config<Q, R>,
+    query_log: bool,
+}
+
+/// The configuration shared by every iteration of a graph.
+pub(crate) struct Config<Q, R> {
+    pub(crate) change_detector: Box<dyn ChangeDetector<R>>,
+    pub(crate) compressor: Option<Box<dyn Compressor<R>>>,
+    pub(crate) progress: Option<(Box<dyn ProgressSink>, usize)>,
+    pub(crate) on_evict: Option<EvictCallback<Q>>,
+    pub(crate) on_resolve: Option<ResolveCallback<Q>>,
+    pub(crate) verifier: Option<Verifier<Q, R>>,
+    pub(crate) node_store: Option<NodeStoreFactory<Q, R>>,
+    pub(crate) singleton: bool,
+    pub(crate) cycle_thrower: Option<CycleThrower<Q>>,
+    pub(crate) max_nodes: Option<usize>,
+    pub(crate) await_old_results: Option<Duration>,
+    pub(crate) validation_strategy: ValidationStrategy,
+    pub(crate) sequential: bool,
+    pub(crate) keep_unchanged_results: bool,
+    pub(crate) edge_pool: Option<EdgePool<Q, R>>,
+    pub(crate) redaction: Redaction<Q, R>,
+    /// The factor (of the average fan-in) and minimum fan-in of hubs (see
+    /// `GraphBuilder::hub_thresholds`).
+    pub(crate) hub_thresholds: (f64, usize),
+    #[cfg(feature = "debug")]
+    pub(crate) slow_clones: Option<SlowClones<Q>>,
+}
+
+/// How the dependencies of an old node are validated (in parallel) before its
+/// result is reused:
+///
+/// - `FirstChange` (the default) stops validating as soon as a changed
+///   dependency is found, since the query has to be resolved again anyway.
+///   Dependencies that are already being validated still finish.
+///
+/// - `AllChecked` validates every dependency even after one changed, which
+///   takes longer but warms all of them for the queries that need them later.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ValidationStrategy {
+    #[default]
+    FirstChange,
+    AllChecked,
+}
+
+type EvictCallback<Q> = Box<dyn Fn(&Q) + Send + Sync>;
+
+type ResolveCallback<Q> = Box<dyn Fn(&Q, Resolution) + Send + Sync>;
+
+type NodeStoreFactory<Q, R> = Box<dyn Fn() -> Box<dyn NodeStore<Q, NodeRef<Q, R>>> + Send + Sync>;
+
+impl<Q: Clone + Eq + Hash, R> Config<Q, R> {
+    /// Creates an empty map for the nodes of an iteration.
+    pub(crate) fn new_node_map(&self) -> NodeMap<Q, NodeRef<Q, R>> {
+        match &self.node_store {
+            _ if self.singleton => NodeMap::Singleton(OnceLock::new()),
+            Some(node_store) => NodeMap::Custom(node_store()),
+            None => NodeMap::Concurrent(ConcurrentMap::new()),
+        }
+    }
+}
+
+impl<Q, R: PartialEq> Default for GraphBuilder<Q, R> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<Q, R: PartialEq> GraphBuilder<Q, R> {
+    /// Creates a builder that detects changed results with [`Equality`].
+    pub fn new() -> Self {
+        Self::with_change_detector(Equality)
+    }
+}
+
+impl<Q, R> GraphBuilder<Q, R> {
+    /// Creates a builder that detects changed results with the given detector.
+    /// Unlike `new` this doesn't require `R: PartialEq`.
+    pub fn with_change_detector(change_detector: impl ChangeDetector<R> + 'static) -> Self {
+        Self {
+            config: Config {
+                change_detector: Box::new(change_detector),
+                compressor: None,
+                progress: None,
+                on_evict: None,
+                on_resolve: None,
+                verifier: None,
+                node_store: None,
+                singleton: false,
+                cycle_thrower: None,
+                max_nodes: None,
+                await_old_results: None,
+                validation_strategy: ValidationStrategy::FirstChange,
+                sequential: false,
+                keep_unchanged_results: false,
+                edge_pool: None,
+                redaction: Redaction::default(),
+                hub_thresholds: (4.0, 8),
+                #[cfg(feature = "debug")]
+                slow_clones: None,
+            },
+            query_log: false,
+        }
+    }
+
+    /// Sets the detector used to decide whether a recomputed result changed.
+    pub fn change_detector(mut self, change_detector: impl ChangeDetector<R> + 'static) -> Self {
+        self.config.change_detector = Box::new(change_detector);
+        self
+    }
+
+    /// Detects changed results by comparing a key derived from them (like a
+    /// version number embedded in the result) instead of the whole results.
+    /// Results with equal keys are considered unchanged, so the key must change
+    /// whenever anything the dependents rely on does.
+    pub fn change_key<K: Eq>(self, key: impl Fn(&R) -> K + Send + Sync + 'static) -> Self {
+        self.change_detector(move |old: &R, new: &R| key(old) != key(new))
+    }
+
+    /// Stores every result compressed with the given compressor, decompressing
+    /// it whenever it's queried.
+    pub fn compressor(mut self, compressor: impl Compressor<R> + 'static) -> Self {
+        self.config.compressor = Some(Box::new(compressor));
+        self
+    }
+
+    /// Reports progress to the sink after every `every` resolved queries, which
+    /// is useful for rendering a progress bar during long cold builds.
+    pub fn progress(mut self, sink: impl ProgressSink + 'static, every: usize) -> Self {
+        assert!(every > 0, "progress must be reported at least every query");
+        self.config.progress = Some((Box::new(sink), every));
+        self
+    }
+
+    /// Calls `on_evict` with every query whose node is dropped from the graph
+    /// by cache management (like `Graph::retain`).
+    pub fn on_evict(mut self, on_evict: impl Fn(&Q) + Send + Sync + 'static) -> Self {
+        self.config.on_evict = Some(Box::new(on_evict));
+        self
+    }
+
+    /// Calls `on_resolve` with every query resolved in every iteration (whether
+    /// it was resolved from scratch, recomputed, or reused), right after it's
+    /// resolved. It's called on the resolving thread, so it should be cheap
+    /// (e.g. just forward to a channel or counter).
+    pub fn on_resolve(
+        mut self,
+        on_resolve: impl Fn(&Q, Resolution) + Send + Sync + 'static,
+    ) -> Self {
+        self.config.on_resolve = Some(Box::new(on_resolve));
+        self
+    }
+
+    /// Enables verification mode: whenever an old result would be reused the
+    /// query is resolved again anyway, and if the result (or the dependencies
+    /// it queries) differ an [`ImpurityReport`](crate::ImpurityReport) is
+    /// recorded (see `Graph::impurity_reports`). The old result is still
+    /// reused, so results are the same as without verification. This defeats
+    /// incrementality, so it's only meant for debugging resolvers.
+    pub fn verify(mut self) -> Self
+    where
+        Q: Debug,
+        R: Debug,
+    {
+        self.config.verifier = Some(Verifier::new());
+        self
+    }
+
+    /// Redacts queries in the `Debug` output the graph produces (impurity
+    /// reports, explanations and the `Debug` impl of the graph itself), to
+    /// keep secrets (like file contents or tokens) out of logs. `redact`
+    /// returns what to show instead of a query (like
+    /// `"GetDocumentContent(<redacted>)"`), or `None` to show its `Debug`
+    /// output as is.
+    ///
+    /// Only the formatted output is redacted: the `Debug` impls of values
+    /// holding queries (like `QueryCycle`, or the `query` of an `Explanation`)
+    /// use the `Debug` impl of the query directly.
+    pub fn redact_queries(
+        mut self,
+        redact: impl Fn(&Q) -> Option<String> + Send + Sync + 'static,
+    ) -> Self {
+        self.config.redaction.query = Some(Box::new(redact));
+        self
+    }
+
+    /// Like `redact_queries`, but for results.
+    pub fn redact_results(
+        mut self,
+        redact: impl Fn(&R) -> Option<String> + Send + Sync + 'static,
+    ) -> Self {
+        self.config.redaction.result = Some(Box::new(redact));
+        self
+    }
+
+    /// Stores the nodes of every iteration in the `NodeStore` of the family
+    /// instead of the default `ConcurrentMap`.
+    pub fn node_store<F: NodeStoreFamily>(mut self) -> Self
+    where
+        Q: Clone + Eq + Hash + Send + Sync + 'static,
+        R: Send + Sync + 'static,
+    {
+        self.config.node_store = Some(Box::new(|| {
+            Box::new(F::Store::<Q, NodeRef<Q, R>>::default())
+        }));
+        self
+    }
+
+    /// Panics with a [`QueryCycle`](crate::QueryCycle) (with the full chain of
+    /// the cycle) when a query depends on itself, instead of a plain message,
+    /// so that `Graph::try_query` can return it as an error.
+    pub fn cycle_errors(mut self) -> Self
+    where
+        Q: Send + 'static,
+    {
+        self.config.cycle_thrower = Some(cycle::throw_cycle::<Q>);
+        self
+    }
+
+    /// Caps the number of nodes in each iteration. Whenever an iteration has
+    /// more, its least recently accessed nodes are evicted (calling the
+    /// eviction callback for each), and are simply resolved again if they're
+    /// queried. Nodes that are being resolved are never evicted.
+    pub fn max_nodes(mut self, max_nodes: usize) -> Self {
+        assert!(
+            max_nodes > 0,
+            "the graph must be able to hold at least one node"
+        );
+        self.config.max_nodes = Some(max_nodes);
+        self
+    }
+
+    /// An advanced tuning knob for iterations incremented while the previous
+    /// iteration is still resolving queries.
+    ///
+    /// When a query is validated while its old node is still being resolved
+    /// by the previous iteration, there's nothing to compare the new result
+    /// against, so it's conservatively considered changed (and everything
+    /// depending on it is resolved again). With this set, the validation
+    /// instead waits up to `timeout` for the old node to be resolved, trading
+    /// some latency for an accurate comparison. This only pays off when
+    /// results are deterministic, otherwise the comparison is likely to report
+    /// a change anyway.
+    pub fn await_old_results(mut self, timeout: Duration) -> Self {
+        self.config.await_old_results = Some(timeout);
+        self
+    }
+
+    /// Sets how the dependencies of old nodes are validated (see
+    /// [`ValidationStrategy`]).
+    pub fn validation_strategy(mut self, strategy: ValidationStrategy) -> Self {
+        self.config.validation_strategy = strategy;
+        self
+    }
+
+    /// Validates the dependencies of old nodes one at a time on the resolving
+    /// thread, instead of in parallel. The resolver still has to be `Send` and
+    /// `Sync` (see [`LocalGraph`](crate::LocalGraph) for resolvers that aren't).
+    pub fn sequential(mut self) -> Self {
+        self.config.sequential = true;
+        self
+    }
+
+    /// Keeps the old result whenever a recomputed result is unchanged (according
+    /// to the change detector), dropping the new one. This preserves the
+    /// identity of results across iterations (e.g. an `Arc` inside the result
+    /// keeps pointing to the same allocation), so the fast paths of downstream
+    /// caches that compare by pointer keep working.
+    pub fn keep_unchanged_results(mut self) -> Self {
+        self.config.keep_unchanged_results = true;
+        self
+    }
+
+    /// Sets how far above average the fan-in of a query has to be for
+    /// `Graph::incrementality_warnings` to flag it as a hub: at least `factor`
+    /// times the average fan-in, and at least `min_fan_in`. The defaults (4
+    /// times the average, and at least 8) flag the aggregates of typical
+    /// graphs, while the minimum keeps small graphs (where every fan-in is
+    /// small) from being flagged at all.
+    pub fn hub_thresholds(mut self, factor: f64, min_fan_in: usize) -> Self {
+        self.config.hub_thresholds = (factor, min_fan_in);
+        self
+    }
+
+    /// Calls `on_slow_clone` with the query (and how long it took) whenever
+    /// cloning a result for `query` takes longer than `threshold`. Since every
+    /// `query` clones its result, a large result that isn't behind an `Arc`
+    /// silently costs a lot, and this points at the queries that do.
+    #[cfg(feature = "debug")]
+    pub fn detect_slow_clones(
+        mut self,
+        threshold: Duration,
+        on_slow_clone: impl Fn(&Q, Duration) + Send + Sync + 'static,
+    ) -> Self {
+        self.config.slow_clones = Some(SlowClones {
+            threshold,
+            on_slow_clone: Box::new(on_slow_clone),
+        });
+        self
+    }
+
+    /// Records every top-level query into a [`QueryLog`].
+    pub fn query_log(mut self) -> Self {
+        self.query_log = true;
+        self
+    }
+}
+
+impl<R> GraphBuilder<(), R> {
+    /// Stores the node of the single (unit) query of every iteration in a
+    /// once-lock instead of a map (see `Graph::singleton`). The node is never
+    /// dropped, so `Graph::retain` and eviction always keep it.
+    pub fn singleton(mut self) -> Self {
+        self.config.singleton = true;
+        self
+    }
+}
+
+impl<Q: Clone + Eq + Hash + Send + Sync, R: Send + Sync> GraphBuilder<Q, R> {
+    /// Pools the edge sets (the dependencies of the nodes) of dropped
+    /// iterations, keeping up to `capacity` of them, and reuses them (cleared)
+    /// for new resolutions instead of allocating new ones. Each resolution
+    /// takes the smallest pooled set that fits the edges it's expected to
+    /// have. This saves an allocation per resolution when incrementing rapidly
+    /// (e.g. on every keystroke), at the cost of keeping the pooled sets (and
+    /// their memory) alive for as long as the graph. How much that matters
+    /// depends on the resolver's own allocations (see the `pool` benchmark).
+    ///
+    /// An iteration's old map is recycled when the iteration is dropped after
+    /// the previous one (so nothing else can use the old nodes anymore). Edge
+    /// sets shared with a newer node (because it was reused) aren't recycled,
+    /// and the nodes themselves aren't pooled.
+    pub fn pool_edge_sets(mut self, capacity: usize) -> Self {
+        self.config.edge_pool = Some(EdgePool::new(capacity));
+        self
+    }
+
+    pub fn build(self, resolver: impl ResolveQuery<Q, R> + 'static) -> Arc<Graph<Q, R>> {
+        let graph = Graph::create(
+            Arc::new(self.config.new_node_map()),
+            Default::default(),
+            Arc::new(resolver),
+            Arc::new(self.config),
+            Shared {
+                log: self
+                    .query_log
+                    .then(|| Arc::new(Mutex::new(QueryLog::new()))),
+                ..Default::default()
+            },
+        );
+
+        graph.become_latest();
+        graph
+    }
+
+    /// Builds the graph from a snapshot (see `Graph::snapshot`). The nodes of
+    /// the snapshot are the old iteration of the graph, so they're validated
+    /// with the resolver (exactly like after an `increment`) instead of being
+    /// resolved from scratch.
+    pub fn build_from_snapshot(
+        self,
+        snapshot: Snapshot<Q, R>,
+        resolver: impl ResolveQuery<Q, R> + 'static,
+    ) -> Arc<Graph<Q, R>> {
+        let graph = self.build(resolver);
+        graph.restore(snapshot);
+        graph
+    }
+}
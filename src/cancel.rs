@@ -0,0 +1,148 @@
+use std::{
+    any::Any,
+    hash::Hash,
+    panic::{self, UnwindSafe},
+    sync::{atomic::Ordering, Arc},
+};
+
+use crate::{Graph, QueryResolver, ResolveQuery};
+
+/// The payload a resolution unwinds with when its iteration is cancelled (see
+/// `Graph::cancel`). It's raised with `resume_unwind`, so it doesn't run the
+/// panic hook (and doesn't print a panic message).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cancelled;
+
+impl Cancelled {
+    /// Unwinds with `Cancelled` as the payload.
+    pub fn throw() -> ! {
+        panic::resume_unwind(Box::new(Cancelled))
+    }
+
+    /// Runs `f`, catching the unwinding if the iteration it queries is
+    /// cancelled. Any other panic is resumed as is.
+    pub fn catch<T>(f: impl FnOnce() -> T + UnwindSafe) -> Result<T, Cancelled> {
+        panic::catch_unwind(f).map_err(|payload: Box<dyn Any + Send>| {
+            match payload.downcast::<Cancelled>() {
+                Ok(cancelled) => *cancelled,
+                Err(payload) => panic::resume_unwind(payload),
+            }
+        })
+    }
+}
+
+impl<Q: Clone + Eq + Hash + Send + Sync, R: Send + Sync> Graph<Q, R> {
+    /// Cancels this iteration: every query resolved in it from now on unwinds
+    /// with [`Cancelled`] instead (including the ones currently in flight, as
+    /// soon as they query another unresolved query or check with
+    /// `QueryResolver::check_cancelled`). Cancellation is cooperative, a
+    /// resolver that's busy computing keeps going until it checks.
+    ///
+    /// Results that were already resolved remain cached and usable, both in
+    /// this iteration and (through validation) in the iterations incremented
+    /// from it.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// Cancels this iteration (see `cancel`) and increments it, which is what
+    /// an editor wants on every change: the work on the stale iteration stops
+    /// and the new one validates against everything it managed to resolve.
+    pub fn cancel_and_increment(
+        self: &Arc<Self>,
+        resolver: impl ResolveQuery<Q, R> + 'static,
+    ) -> Arc<Self> {
+        self.cancel();
+        self.increment(resolver)
+    }
+
+    /// Unwinds with [`Cancelled`] if this iteration was cancelled.
+    pub(crate) fn check_cancelled(&self) {
+        if self.is_cancelled() {
+            Cancelled::throw();
+        }
+    }
+}
+
+impl<Q: Clone + Eq + Hash + Send + Sync, R: Send + Sync> QueryResolver<Q, R> {
+    /// Unwinds with [`Cancelled`] if the iteration being resolved was
+    /// cancelled. Long running resolvers should call this every so often, so
+    /// cancellation doesn't have to wait for them to query something.
+    pub fn check_cancelled(&self) {
+        self.graph.check_cancelled();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::panic::AssertUnwindSafe;
+
+    use parking_lot::Mutex;
+
+    use super::*;
+
+    #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+    enum Query {
+        Sum,
+        A,
+        B,
+    }
+
+    /// `Sum` adds the inputs `A` and `B`, and resolving `cancel_on` cancels
+    /// the iteration it's resolved in.
+    #[derive(Clone, Default)]
+    struct Resolver {
+        cancel_on: Arc<Mutex<Option<Query>>>,
+        resolved: Arc<Mutex<Vec<Query>>>,
+    }
+
+    impl ResolveQuery<Query, u32> for Resolver {
+        fn resolve(&self, q: Query, resolve: Arc<QueryResolver<Query, u32>>) -> u32 {
+            if self.cancel_on.lock().as_ref() == Some(&q) {
+                resolve.graph.cancel();
+                resolve.check_cancelled();
+            }
+
+            self.resolved.lock().push(q.clone());
+
+            match q {
+                Query::Sum => resolve.query(Query::A) + resolve.query(Query::B),
+                Query::A | Query::B => 1,
+            }
+        }
+
+        fn is_input(&self, q: &Query) -> bool {
+            *q != Query::Sum
+        }
+    }
+
+    #[test]
+    fn cancelled_iterations_keep_their_results() {
+        let resolver = Resolver::default();
+        let graph = Graph::new(resolver.clone());
+
+        assert_eq!(graph.query(Query::A), 1);
+
+        *resolver.cancel_on.lock() = Some(Query::B);
+        let cancelled = Cancelled::catch(AssertUnwindSafe(|| graph.query(Query::Sum)));
+
+        assert_eq!(cancelled, Err(Cancelled));
+        assert!(graph.is_cancelled());
+        assert_eq!(graph.edges_of(&Query::Sum), None);
+        assert_eq!(graph.query(Query::A), 1);
+
+        // The next iteration reuses what the cancelled one resolved.
+        *resolver.cancel_on.lock() = None;
+        resolver.resolved.lock().clear();
+
+        let graph = graph.cancel_and_increment(resolver.clone());
+
+        assert!(!graph.is_cancelled());
+        assert_eq!(graph.query(Query::Sum), 2);
+        assert_eq!(*resolver.resolved.lock(), [Query::Sum, Query::B]);
+    }
+}
@@ -0,0 +1,284 @@
+use std::{
+    hash::{DefaultHasher, Hash, Hasher},
+    sync::Arc,
+};
+
+/// A `ChangeDetector` decides whether a query's result changed between two
+/// iterations of the graph.
+///
+/// This decision drives early cutoff: when a query is resolved again and the
+/// detector reports that its result is unchanged, none of the queries that
+/// depend on it have to be resolved again. A detector that reports a change
+/// when there was none is still correct (it just does more work), while one
+/// that misses a real change will cause stale results to be reused.
+///
+/// Any `Fn(&R, &R) -> bool + Send + Sync` closure can be used as a detector.
+pub trait ChangeDetector<R>: Send + Sync {
+    /// Returns `true` if `new` should be considered a different result than `old`.
+    fn changed(&self, old: &R, new: &R) -> bool;
+}
+
+impl<R, F: Fn(&R, &R) -> bool + Send + Sync> ChangeDetector<R> for F {
+    fn changed(&self, old: &R, new: &R) -> bool {
+        self(old, new)
+    }
+}
+
+/// The default detector, which compares results with `PartialEq`.
+///
+/// # NaN
+///
+/// Results that contain floats can't implement `Eq`, and with `PartialEq`
+/// `NaN != NaN`. This means a result containing a `NaN` is *always* reported
+/// as changed, even when it was recomputed to the exact same value, which
+/// silently defeats incrementality for it and everything depending on it. Use
+/// [`BitEquality`] for such results.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Equality;
+
+impl<R: PartialEq> ChangeDetector<R> for Equality {
+    fn changed(&self, old: &R, new: &R) -> bool {
+        old != new
+    }
+}
+
+/// A `ChangePolicy` is a simple way to pick how a graph detects changed
+/// results, and so how early cutoff behaves:
+///
+/// - `Equality` compares results with `PartialEq` (like [`Equality`]). Only
+///   the dependents of results that actually changed are resolved again.
+///
+/// - `AlwaysChanged` treats every recomputed result as changed, which disables
+///   early cutoff: recomputing a query always recomputes its dependents.
+///
+/// - `NeverChanged` treats every recomputed result as unchanged, so the
+///   dependents of a recomputed query are never recomputed because of it.
+///   This is only correct for results that are known to be stable given
+///   stable inputs (and it's the cheapest policy, nothing is compared).
+///
+/// - `Custom` decides with another detector, like a closure that returns
+///   `true` if the result changed.
+///
+/// Since `Equality` is a variant, the policy is only a `ChangeDetector` for
+/// results that implement `PartialEq`. Results that don't can use a closure
+/// directly with `GraphBuilder::with_change_detector` (e.g. `|_, _| true` to
+/// always treat them as changed).
+pub enum ChangePolicy<R> {
+    Equality,
+    AlwaysChanged,
+    NeverChanged,
+    Custom(Box<dyn ChangeDetector<R>>),
+}
+
+impl<R: PartialEq> ChangeDetector<R> for ChangePolicy<R> {
+    fn changed(&self, old: &R, new: &R) -> bool {
+        match self {
+            Self::Equality => old != new,
+            Self::AlwaysChanged => true,
+            Self::NeverChanged => false,
+            Self::Custom(detector) => detector.changed(old, new),
+        }
+    }
+}
+
+/// A `ChangePipeline` detects changes with a series of increasingly expensive
+/// checks before falling back to a final detector (like [`Equality`]). Each
+/// check can only prove that a result changed: the first one that does
+/// short-circuits the rest, otherwise the final detector decides. This makes
+/// the common case (an obvious change) cheap, without missing subtle changes.
+///
+/// ```ignore
+/// let detector = ChangePipeline::new()
+///     .compare_key(|model: &SemanticModel| model.syntax_trees.len())
+///     .compare_hash();
+///
+/// let graph = GraphBuilder::with_change_detector(detector).build(resolver);
+/// ```
+pub struct ChangePipeline<R> {
+    checks: Vec<Check<R>>,
+    detector: Box<dyn ChangeDetector<R>>,
+}
+
+type Check<R> = Box<dyn Fn(&R, &R) -> bool + Send + Sync>;
+
+impl<R: PartialEq> Default for ChangePipeline<R> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<R: PartialEq> ChangePipeline<R> {
+    /// Creates a pipeline (without any checks yet) that falls back to
+    /// [`Equality`].
+    pub fn new() -> Self {
+        Self::ending_with(Equality)
+    }
+}
+
+impl<R> ChangePipeline<R> {
+    /// Creates a pipeline (without any checks yet) that falls back to the
+    /// given detector.
+    pub fn ending_with(detector: impl ChangeDetector<R> + 'static) -> Self {
+        Self {
+            checks: Vec::new(),
+            detector: Box::new(detector),
+        }
+    }
+
+    /// Adds a check that returns `true` if the results are known to differ.
+    /// Returning `false` just moves on to the next check.
+    pub fn check(mut self, differs: impl Fn(&R, &R) -> bool + Send + Sync + 'static) -> Self {
+        self.checks.push(Box::new(differs));
+        self
+    }
+
+    /// Adds a check that compares a cheap key of the results (like a length
+    /// or a count), which differ if their keys do.
+    pub fn compare_key<K: PartialEq>(self, key: impl Fn(&R) -> K + Send + Sync + 'static) -> Self {
+        self.check(move |old, new| key(old) != key(new))
+    }
+
+    /// Adds a check that compares the hashes of the results, which differ if
+    /// their hashes do (equal hashes don't prove anything, so the next checks
+    /// still run).
+    pub fn compare_hash(self) -> Self
+    where
+        R: Hash,
+    {
+        self.check(|old, new| {
+            let hash = |result: &R| {
+                let mut hasher = DefaultHasher::new();
+                result.hash(&mut hasher);
+                hasher.finish()
+            };
+
+            hash(old) != hash(new)
+        })
+    }
+}
+
+impl<R> ChangeDetector<R> for ChangePipeline<R> {
+    fn changed(&self, old: &R, new: &R) -> bool {
+        self.checks.iter().any(|differs| differs(old, new)) || self.detector.changed(old, new)
+    }
+}
+
+/// A detector that compares results with [`BitEq`], so floats are compared by
+/// their bit patterns. An identically recomputed `NaN` is unchanged, while
+/// `0.0` and `-0.0` are considered different.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BitEquality;
+
+impl<R: BitEq> ChangeDetector<R> for BitEquality {
+    fn changed(&self, old: &R, new: &R) -> bool {
+        !old.bit_eq(new)
+    }
+}
+
+/// Equality where floats are compared by their bit patterns (`to_bits`)
+/// instead of by value. Unlike `PartialEq` this is reflexive for every value,
+/// including `NaN`.
+pub trait BitEq {
+    fn bit_eq(&self, other: &Self) -> bool;
+}
+
+impl BitEq for f32 {
+    fn bit_eq(&self, other: &Self) -> bool {
+        self.to_bits() == other.to_bits()
+    }
+}
+
+impl BitEq for f64 {
+    fn bit_eq(&self, other: &Self) -> bool {
+        self.to_bits() == other.to_bits()
+    }
+}
+
+macro_rules! impl_bit_eq_for_eq {
+    ($($t:ty),*) => {
+        $(
+            impl BitEq for $t {
+                fn bit_eq(&self, other: &Self) -> bool {
+                    self == other
+                }
+            }
+        )*
+    };
+}
+
+impl_bit_eq_for_eq!(
+    (),
+    bool,
+    char,
+    u8,
+    u16,
+    u32,
+    u64,
+    u128,
+    usize,
+    i8,
+    i16,
+    i32,
+    i64,
+    i128,
+    isize,
+    str,
+    String
+);
+
+impl<T: BitEq> BitEq for [T] {
+    fn bit_eq(&self, other: &Self) -> bool {
+        self.len() == other.len() && self.iter().zip(other).all(|(a, b)| a.bit_eq(b))
+    }
+}
+
+impl<T: BitEq, const N: usize> BitEq for [T; N] {
+    fn bit_eq(&self, other: &Self) -> bool {
+        self.as_slice().bit_eq(other.as_slice())
+    }
+}
+
+impl<T: BitEq> BitEq for Vec<T> {
+    fn bit_eq(&self, other: &Self) -> bool {
+        self.as_slice().bit_eq(other.as_slice())
+    }
+}
+
+impl<T: BitEq> BitEq for Option<T> {
+    fn bit_eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Some(a), Some(b)) => a.bit_eq(b),
+            (None, None) => true,
+            _ => false,
+        }
+    }
+}
+
+impl<T: BitEq + ?Sized> BitEq for Box<T> {
+    fn bit_eq(&self, other: &Self) -> bool {
+        self.as_ref().bit_eq(other.as_ref())
+    }
+}
+
+impl<T: BitEq + ?Sized> BitEq for Arc<T> {
+    fn bit_eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(self, other) || self.as_ref().bit_eq(other.as_ref())
+    }
+}
+
+macro_rules! impl_bit_eq_for_tuple {
+    ($($name:ident $idx:tt),+) => {
+        impl<$($name: BitEq),+> BitEq for ($($name,)+) {
+            fn bit_eq(&self, other: &Self) -> bool {
+                $(self.$idx.bit_eq(&other.$idx))&&+
+            }
+        }
+    };
+}
+
+impl_bit_eq_for_tuple!(A 0);
+impl_bit_eq_for_tuple!(A 0, B 1);
+impl_bit_eq_for_tuple!(A 0, B 1, C 2);
+impl_bit_eq_for_tuple!(A 0, B 1, C 2, D 3);
+impl_bit_eq_for_tuple!(A 0, B 1, C 2, D 3, E 4);
+impl_bit_eq_for_tuple!(A 0, B 1, C 2, D 3, E 4, F 5);
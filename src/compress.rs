@@ -0,0 +1,31 @@
+use std::sync::Arc;
+
+/// A `Compressor` stores results in a compressed form (typically by serializing
+/// and then compressing them) to reduce the memory used by very large caches.
+///
+/// When a graph is built with a compressor every result is compressed when
+/// it's resolved and decompressed every time it's queried, trading CPU time
+/// for memory. Compression should be deterministic: when a recomputed result
+/// compresses to the same bytes as the old one it's considered unchanged
+/// without decompressing anything, otherwise both are decompressed and
+/// compared with the graph's `ChangeDetector`.
+pub trait Compressor<R>: Send + Sync {
+    fn compress(&self, result: &R) -> Vec<u8>;
+    fn decompress(&self, bytes: &[u8]) -> R;
+}
+
+/// The form a result is stored in inside a node.
+#[derive(Debug)]
+pub(crate) enum Stored<R> {
+    Value(Arc<R>),
+    Compressed(Arc<[u8]>),
+}
+
+impl<R> Clone for Stored<R> {
+    fn clone(&self) -> Self {
+        match self {
+            Self::Value(result) => Self::Value(result.clone()),
+            Self::Compressed(bytes) => Self::Compressed(bytes.clone()),
+        }
+    }
+}
@@ -0,0 +1,30 @@
+use std::{any::Any, hash::Hash, sync::Arc};
+
+use crate::{cycle::Path, Graph, QueryResolver};
+
+impl<Q: Clone + Eq + Hash + Send + Sync, R: Clone + Send + Sync> Graph<Q, R> {
+    /// Like `query`, but hands `ctx` to the resolutions it makes (see
+    /// `QueryResolver::context`), like a logger or a request id. The context
+    /// isn't part of the query, so it's neither a dependency nor considered by
+    /// change detection, and it **must not** affect the result: results are
+    /// cached (and reused) no matter which context they were resolved with.
+    ///
+    /// Only the queries actually resolved by this call see the context.
+    /// Queries that were already resolved (or are being resolved by another
+    /// call) are reused without it.
+    pub fn query_with_ctx<C: Send + Sync + 'static>(self: &Arc<Self>, q: Q, ctx: C) -> R {
+        self.log_query(&q);
+
+        let path = Path::with_context(Arc::new(ctx));
+        self.query_on(q, &path).as_ref().clone()
+    }
+}
+
+impl<Q, R> QueryResolver<Q, R> {
+    /// Returns the context of the query this resolution was made for (see
+    /// `Graph::query_with_ctx`), or `None` if it has none (or it isn't a `C`).
+    pub fn context<C: 'static>(&self) -> Option<&C> {
+        let context: &(dyn Any + Send + Sync) = self.path.context.as_deref()?;
+        context.downcast_ref()
+    }
+}
@@ -0,0 +1,287 @@
+use std::{
+    any::Any,
+    backtrace::Backtrace,
+    error::Error,
+    fmt::{self, Debug, Display},
+    hash::Hash,
+    panic::{self, AssertUnwindSafe},
+    sync::Arc,
+};
+
+use crate::{budget::Budget, Graph};
+
+/// The path of queries being resolved that led to a resolution, stored as a
+/// linked list from the innermost query (the one being resolved) outwards,
+/// along with the budget of the top-level query (if it has one), which every
+/// resolution on the path spends from, and its context (see
+/// `Graph::query_with_ctx`).
+pub(crate) struct Path<Q> {
+    head: Option<Arc<PathNode<Q>>>,
+    pub(crate) budget: Option<Arc<Budget>>,
+    pub(crate) context: Option<Arc<dyn Any + Send + Sync>>,
+}
+
+struct PathNode<Q> {
+    q: Q,
+    parent: Option<Arc<PathNode<Q>>>,
+}
+
+impl<Q> Default for Path<Q> {
+    fn default() -> Self {
+        Self {
+            head: None,
+            budget: None,
+            context: None,
+        }
+    }
+}
+
+impl<Q> Clone for Path<Q> {
+    fn clone(&self) -> Self {
+        Self {
+            head: self.head.clone(),
+            budget: self.budget.clone(),
+            context: self.context.clone(),
+        }
+    }
+}
+
+impl<Q> Path<Q> {
+    /// An empty path whose resolutions spend from the budget.
+    pub(crate) fn with_budget(budget: Budget) -> Self {
+        Self {
+            budget: Some(Arc::new(budget)),
+            ..Self::default()
+        }
+    }
+
+    /// An empty path whose resolutions are handed the context.
+    pub(crate) fn with_context(context: Arc<dyn Any + Send + Sync>) -> Self {
+        Self {
+            context: Some(context),
+            ..Self::default()
+        }
+    }
+}
+
+/// Extends the path with the query being resolved.
+pub(crate) fn push<Q>(path: &Path<Q>, q: Q) -> Path<Q> {
+    Path {
+        head: Some(Arc::new(PathNode {
+            q,
+            parent: path.head.clone(),
+        })),
+        budget: path.budget.clone(),
+        context: path.context.clone(),
+    }
+}
+
+/// Returns the cycle (from `q` back to `q`) if `q` is already on the path.
+fn find_cycle<Q: Clone + Eq>(path: &Path<Q>, q: &Q) -> Option<Vec<Q>> {
+    let mut chain = vec![q.clone()];
+    let mut node = path.head.as_deref();
+
+    while let Some(PathNode { q: query, parent }) = node {
+        chain.push(query.clone());
+
+        if query == q {
+            chain.reverse();
+            return Some(chain);
+        }
+
+        node = parent.as_deref();
+    }
+
+    None
+}
+
+/// The error of a query that (transitively) depends on itself, which would
+/// otherwise deadlock. The chain starts and ends with the same query, and each
+/// query in it is queried by the one before it.
+#[derive(Clone)]
+pub struct QueryCycle<Q> {
+    pub chain: Vec<Q>,
+    /// The backtrace of where the cycle was detected (the query that closed
+    /// it). It's only captured if backtraces are enabled (see
+    /// `Backtrace::capture`).
+    pub backtrace: Arc<Backtrace>,
+}
+
+impl<Q: Debug> Display for QueryCycle<Q> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "query cycle: ")?;
+
+        for (i, q) in self.chain.iter().enumerate() {
+            if i > 0 {
+                write!(f, " -> ")?;
+            }
+
+            write!(f, "{:?}", q)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<Q: Debug> Debug for QueryCycle<Q> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("QueryCycle")
+            .field("chain", &self.chain)
+            .field("backtrace", &self.backtrace)
+            .finish()
+    }
+}
+
+impl<Q: Debug> Error for QueryCycle<Q> {}
+
+/// Raises a detected cycle. It's captured by the `GraphBuilder` so that only
+/// typed cycle errors require `Q: 'static`.
+pub(crate) type CycleThrower<Q> = fn(Vec<Q>) -> !;
+
+pub(crate) fn throw_cycle<Q: Send + 'static>(chain: Vec<Q>) -> ! {
+    panic::panic_any(QueryCycle {
+        chain,
+        backtrace: Arc::new(Backtrace::capture()),
+    })
+}
+
+impl<Q: Clone + Eq + Hash + Send + Sync, R: Send + Sync> Graph<Q, R> {
+    /// Checks whether resolving `q` on the path would close a cycle. With
+    /// `recover` the resolver gets to recover from it first (see
+    /// `ResolveQuery::recover_cycle`), and the recovered result is returned.
+    /// Otherwise it panics: without `GraphBuilder::cycle_errors` the panic is
+    /// a plain message, otherwise its payload is the [`QueryCycle`].
+    pub(crate) fn check_cycle(&self, path: &Path<Q>, q: &Q, recover: bool) -> Option<Arc<R>> {
+        let chain = find_cycle(path, q)?;
+
+        if recover {
+            if let Some(result) = self.resolver.recover_cycle(q, &chain) {
+                return Some(Arc::new(result));
+            }
+        }
+
+        match self.config.cycle_thrower {
+            Some(throw) => throw(chain),
+            None => panic!(
+                "query cycle detected (build the graph with \
+                 `GraphBuilder::cycle_errors` to get the chain)"
+            ),
+        }
+    }
+}
+
+impl<Q: Clone + Eq + Hash + Send + Sync + 'static, R: Clone + Send + Sync> Graph<Q, R> {
+    /// Like `query`, but returns the cycle as an error if the query (or any
+    /// query it depends on) depends on itself. This requires the graph to be
+    /// built with `GraphBuilder::cycle_errors`, otherwise cycles just panic.
+    /// Any other panic is resumed as is.
+    ///
+    /// The queries on the cycle are left unresolved, so they (and anything
+    /// depending on them) fail the same way if they're queried again.
+    pub fn try_query(self: &Arc<Self>, q: Q) -> Result<R, QueryCycle<Q>> {
+        panic::catch_unwind(AssertUnwindSafe(|| self.query(q))).map_err(
+            |payload: Box<dyn Any + Send>| match payload.downcast::<QueryCycle<Q>>() {
+                Ok(cycle) => *cycle,
+                Err(payload) => panic::resume_unwind(payload),
+            },
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use parking_lot::Mutex;
+
+    use crate::{GraphBuilder, QueryResolver, ResolveQuery};
+
+    use super::*;
+
+    /// `Itself` depends on itself, and `A`, `B` and `C` depend on each other in
+    /// the cycle `A -> B -> C -> A`.
+    #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+    enum Query {
+        Itself,
+        A,
+        B,
+        C,
+    }
+
+    struct Resolver;
+
+    impl ResolveQuery<Query, u32> for Resolver {
+        fn resolve(&self, q: Query, resolve: Arc<QueryResolver<Query, u32>>) -> u32 {
+            match q {
+                Query::Itself => resolve.query(Query::Itself),
+                Query::A => resolve.query(Query::B),
+                Query::B => resolve.query(Query::C),
+                Query::C => resolve.query(Query::A),
+            }
+        }
+    }
+
+    #[test]
+    fn self_cycle() {
+        let graph = GraphBuilder::new().cycle_errors().build(Resolver);
+        let cycle = graph.try_query(Query::Itself).unwrap_err();
+
+        assert_eq!(cycle.chain, [Query::Itself, Query::Itself]);
+        assert_eq!(cycle.to_string(), "query cycle: Itself -> Itself");
+    }
+
+    #[test]
+    fn multi_node_cycle() {
+        let graph = GraphBuilder::new().cycle_errors().build(Resolver);
+        let cycle = graph.try_query(Query::A).unwrap_err();
+
+        assert_eq!(cycle.chain, [Query::A, Query::B, Query::C, Query::A]);
+        assert_eq!(cycle.to_string(), "query cycle: A -> B -> C -> A");
+
+        // The queries on the cycle are left unresolved, so querying one of them
+        // again reports the cycle from there.
+        let cycle = graph.try_query(Query::B).unwrap_err();
+        assert_eq!(cycle.chain, [Query::B, Query::C, Query::A, Query::B]);
+    }
+
+    /// Like `Resolver`, but each query adds one to the query it depends on,
+    /// and cycles are broken with 0 (recording the cycles it was asked about).
+    #[derive(Clone, Default)]
+    struct RecoveringResolver {
+        cycles: Arc<Mutex<Vec<Vec<Query>>>>,
+    }
+
+    impl ResolveQuery<Query, u32> for RecoveringResolver {
+        fn resolve(&self, q: Query, resolve: Arc<QueryResolver<Query, u32>>) -> u32 {
+            Resolver.resolve(q, resolve) + 1
+        }
+
+        fn recover_cycle(&self, _q: &Query, cycle: &[Query]) -> Option<u32> {
+            self.cycles.lock().push(cycle.to_vec());
+            Some(0)
+        }
+    }
+
+    #[test]
+    fn recovered_cycles() {
+        let resolver = RecoveringResolver::default();
+        let graph = GraphBuilder::new().build(resolver.clone());
+
+        // `C` gets the recovered 0 for `A`, so `A` is 3.
+        assert_eq!(graph.query(Query::A), 3);
+        assert_eq!(graph.query(Query::B), 2);
+        assert_eq!(graph.query(Query::C), 1);
+
+        // The query closing the cycle doesn't depend on the recovered one.
+        assert_eq!(graph.edges_of(&Query::C), Some(Default::default()));
+        assert_eq!(graph.edges_of(&Query::A), Some([Query::B].into()));
+
+        assert_eq!(graph.query(Query::Itself), 1);
+
+        assert_eq!(
+            *resolver.cycles.lock(),
+            [
+                vec![Query::A, Query::B, Query::C, Query::A],
+                vec![Query::Itself, Query::Itself],
+            ]
+        );
+    }
+}
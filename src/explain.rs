@@ -0,0 +1,133 @@
+use std::{
+    fmt::{self, Debug, Display},
+    hash::Hash,
+};
+
+use crate::{stats::Resolution, Graph};
+
+/// Why a query has the result it has in an iteration: how it was resolved,
+/// along with the explanations of its dependencies (see `Graph::explain`).
+///
+/// Its `Display` output is an indented tree, one query per line:
+///
+/// ```text
+/// Typecheck("main") [recomputed, changed] = Ok(())
+///   Parse("main") [recomputed, changed] = Ast { .. }
+///     Source("main") [fresh] = "fn main() {}"
+///   Parse("lib") [reused] = Ast { .. }
+///     ...
+/// ```
+#[derive(Debug, Clone)]
+pub struct Explanation<Q> {
+    pub query: Q,
+    /// The `Debug` output of the query (unless it's redacted, see
+    /// `GraphBuilder::redact_queries`), which is what `Display` shows.
+    pub label: String,
+    /// The `Debug` output of the result (unless it's redacted), or `None` if
+    /// the query isn't resolved in this iteration (so nothing else about it
+    /// is known either).
+    pub result: Option<String>,
+    /// How the query was resolved in this iteration.
+    pub resolution: Option<Resolution>,
+    /// Whether its result changed since the previous iteration.
+    pub changed: bool,
+    pub dependencies: Vec<Explanation<Q>>,
+    /// Whether the dependencies were left out because of the depth limit.
+    pub truncated: bool,
+}
+
+impl<Q> Explanation<Q> {
+    fn fmt_indented(&self, f: &mut fmt::Formatter<'_>, indent: usize) -> fmt::Result {
+        write!(f, "{:indent$}{}", "", self.label, indent = indent * 2)?;
+
+        match (&self.result, self.resolution) {
+            (Some(result), Some(resolution)) => {
+                let resolution = match resolution {
+                    Resolution::Fresh => "fresh",
+                    Resolution::Recomputed => "recomputed",
+                    Resolution::Reused => "reused",
+                };
+
+                if self.changed {
+                    write!(f, " [{}, changed] = {}", resolution, result)?;
+                } else {
+                    write!(f, " [{}] = {}", resolution, result)?;
+                }
+            }
+            _ => write!(f, " [unresolved]")?,
+        }
+
+        writeln!(f)?;
+
+        for dependency in &self.dependencies {
+            dependency.fmt_indented(f, indent + 1)?;
+        }
+
+        if self.truncated {
+            writeln!(f, "{:indent$}...", "", indent = (indent + 1) * 2)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<Q> Display for Explanation<Q> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.fmt_indented(f, 0)
+    }
+}
+
+impl<Q: Clone + Eq + Hash + Send + Sync, R: Send + Sync> Graph<Q, R> {
+    /// Explains the result of the query in this iteration: how it (and each of
+    /// its dependencies, recursively) was resolved, and whether its result
+    /// changed. This is the place to start when a query is recomputed (or
+    /// reused) unexpectedly, since the changed dependencies show why.
+    ///
+    /// Dependencies are explained up to `max_depth` levels deep (the query
+    /// itself is at depth `0`). A dependency shared by several queries is
+    /// explained under each of them. Nothing is resolved, so queries that
+    /// aren't resolved in this iteration are explained as unresolved.
+    pub fn explain(&self, q: &Q, max_depth: usize) -> Explanation<Q>
+    where
+        Q: Debug,
+        R: Debug,
+    {
+        let slot = self.new.get(q);
+        let label = self.describe_query(q, |q| format!("{:?}", q));
+
+        let Some(node) = slot.as_ref().and_then(|slot| slot.get()) else {
+            return Explanation {
+                query: q.clone(),
+                label,
+                result: None,
+                resolution: None,
+                changed: false,
+                dependencies: Vec::new(),
+                truncated: false,
+            };
+        };
+
+        let truncated = max_depth == 0 && !node.edges_from.is_empty();
+
+        let dependencies = if max_depth == 0 {
+            Vec::new()
+        } else {
+            node.edges_from
+                .iter()
+                .map(|parent| self.explain(parent, max_depth - 1))
+                .collect()
+        };
+
+        Explanation {
+            query: q.clone(),
+            label,
+            result: Some(
+                self.describe_result(&self.load(&node.result), |result| format!("{:?}", result)),
+            ),
+            resolution: Some(node.resolution),
+            changed: node.changed,
+            dependencies,
+            truncated,
+        }
+    }
+}
@@ -0,0 +1,95 @@
+use std::{any::type_name, fmt::Debug};
+
+/// Helpers for working with results that are enums with one variant per query
+/// (like the `QueryResult` enum in the example).
+pub trait QueryResultExt: Debug {
+    /// Downcasts the result using `downcast` (typically an `as_*` accessor like
+    /// the ones generated by `enum-as-inner`). If the downcast fails this panics
+    /// with the variant that was actually found (and the type the downcast was
+    /// for), instead of the bare `unwrap` on `None` message.
+    #[track_caller]
+    fn expect_variant<'a, T>(&'a self, downcast: impl FnOnce(&'a Self) -> Option<T>) -> T {
+        match downcast(self) {
+            Some(value) => value,
+            None => panic!(
+                "expected a result that downcasts to `{}`, found the `{}` variant",
+                type_name::<T>(),
+                variant_name(self)
+            ),
+        }
+    }
+}
+
+impl<R: Debug + ?Sized> QueryResultExt for R {}
+
+/// Gets the name of the variant from the `Debug` output (everything before
+/// the variant's fields), so huge results don't flood the panic message.
+fn variant_name<R: Debug + ?Sized>(result: &R) -> String {
+    let debug = format!("{:?}", result);
+
+    match debug.find(['(', '{', ' ']) {
+        Some(end) => debug[..end].to_owned(),
+        None => debug,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::panic;
+
+    use super::*;
+
+    #[derive(Debug)]
+    enum QueryResult {
+        Source(String),
+        Lines { count: usize },
+        Empty,
+    }
+
+    impl QueryResult {
+        fn as_source(&self) -> Option<&String> {
+            match self {
+                Self::Source(source) => Some(source),
+                _ => None,
+            }
+        }
+
+        fn as_lines(&self) -> Option<usize> {
+            match self {
+                Self::Lines { count } => Some(*count),
+                _ => None,
+            }
+        }
+    }
+
+    fn panic_message(result: &QueryResult) -> String {
+        let payload = panic::catch_unwind(|| {
+            result.expect_variant(QueryResult::as_source);
+        })
+        .unwrap_err();
+
+        *payload.downcast::<String>().unwrap()
+    }
+
+    #[test]
+    fn downcasts_report_the_actual_variant() {
+        let source = QueryResult::Source("fn main() {}".to_owned());
+        assert_eq!(
+            source.expect_variant(QueryResult::as_source),
+            "fn main() {}"
+        );
+        assert_eq!(
+            QueryResult::Lines { count: 3 }.expect_variant(QueryResult::as_lines),
+            3
+        );
+
+        assert_eq!(
+            panic_message(&QueryResult::Lines { count: 3 }),
+            "expected a result that downcasts to `&alloc::string::String`, found the `Lines` variant"
+        );
+        assert_eq!(
+            panic_message(&QueryResult::Empty),
+            "expected a result that downcasts to `&alloc::string::String`, found the `Empty` variant"
+        );
+    }
+}
@@ -0,0 +1,160 @@
+use std::{
+    fmt::{self, Debug},
+    hash::Hash,
+    sync::{Arc, Weak},
+};
+
+use parking_lot::Mutex;
+
+use crate::{compress::Stored, cycle::Path, Graph, QueryResolver};
+
+/// Every iteration of a graph shares its lineage, which points to the latest
+/// iteration, so that other graphs depending on it can find the latest results.
+pub(crate) type Lineage<Q, R> = Arc<Mutex<Weak<Graph<Q, R>>>>;
+
+/// The external dependencies of a node (queries on other graphs).
+pub(crate) type ExternalEdges = Arc<[Arc<dyn ExternalEdge>]>;
+
+/// A dependency on a query of another graph, type erased since that graph has
+/// its own query and result types.
+pub(crate) trait ExternalEdge: Send + Sync {
+    /// Validates the query against the latest iteration of its graph and
+    /// returns whether its result changed since it was queried.
+    fn changed(&self) -> bool;
+}
+
+impl Debug for dyn ExternalEdge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ExternalEdge")
+    }
+}
+
+struct External<Q, R> {
+    lineage: Lineage<Q, R>,
+    q: Q,
+    /// The result the query had when it was queried.
+    result: Stored<R>,
+}
+
+impl<Q: Clone + Eq + Hash + Send + Sync, R: Send + Sync> ExternalEdge for External<Q, R> {
+    fn changed(&self) -> bool {
+        // If every iteration of the graph was dropped there's nothing to
+        // validate against, so the result is considered changed.
+        let Some(latest) = self.lineage.lock().upgrade() else {
+            return true;
+        };
+
+        let slot = latest.get_node(&self.q);
+        let node = latest.get_or_resolve(&slot, self.q.clone(), &Path::default());
+        latest.changed(&self.result, &node.result)
+    }
+}
+
+impl<Q: Clone + Eq + Hash + Send + Sync, R: Send + Sync> QueryResolver<Q, R> {
+    /// Queries a query on another graph (e.g. the graph of another crate),
+    /// recording it as an external dependency. When this graph is incremented
+    /// the query is validated against the latest iteration of the other graph
+    /// (the one most recently incremented from `other`), so changes to it
+    /// propagate to the queries here that depend on it.
+    ///
+    /// External dependencies aren't part of snapshots (or the reverse edges),
+    /// only of validation.
+    pub fn query_external<Q2, R2>(&self, other: &Arc<Graph<Q2, R2>>, q: Q2) -> R2
+    where
+        Q2: Clone + Eq + Hash + Send + Sync + 'static,
+        R2: Clone + Send + Sync + 'static,
+    {
+        let slot = other.get_node(&q);
+        let node = other.get_or_resolve(&slot, q.clone(), &Path::default());
+        let result = other.load(&node.result).as_ref().clone();
+
+        self.external_edges.lock().push(Arc::new(External {
+            lineage: other.lineage.clone(),
+            q,
+            result: node.result.clone(),
+        }));
+
+        result
+    }
+}
+
+impl<Q: Clone + Eq + Hash + Send + Sync, R: Send + Sync> Graph<Q, R> {
+    /// Whether any external dependency of a node changed.
+    pub(crate) fn any_external_changed(external_edges: &ExternalEdges) -> bool {
+        external_edges.iter().any(|edge| edge.changed())
+    }
+
+    /// Makes this iteration the latest one of its lineage.
+    pub(crate) fn become_latest(self: &Arc<Self>) {
+        *self.lineage.lock() = Arc::downgrade(self);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ResolveQuery;
+
+    use super::*;
+
+    /// The inputs of another graph (say, the graph of a dependency).
+    #[derive(Clone, Default)]
+    struct Inputs {
+        values: Arc<Mutex<Vec<u32>>>,
+    }
+
+    impl ResolveQuery<usize, u32> for Inputs {
+        fn resolve(&self, q: usize, _resolve: Arc<QueryResolver<usize, u32>>) -> u32 {
+            self.values.lock()[q]
+        }
+
+        fn is_input(&self, _q: &usize) -> bool {
+            true
+        }
+    }
+
+    /// Doubles the inputs of the other graph.
+    struct Doubler {
+        other: Arc<Graph<usize, u32>>,
+        resolved: Arc<Mutex<Vec<usize>>>,
+    }
+
+    impl ResolveQuery<usize, u32> for Doubler {
+        fn resolve(&self, q: usize, resolve: Arc<QueryResolver<usize, u32>>) -> u32 {
+            self.resolved.lock().push(q);
+            resolve.query_external(&self.other, q) * 2
+        }
+    }
+
+    #[test]
+    fn external_changes_propagate() {
+        let inputs = Inputs::default();
+        *inputs.values.lock() = vec![1, 2];
+
+        let other = Graph::new(inputs.clone());
+        let resolved = Arc::new(Mutex::new(Vec::new()));
+
+        let graph = Graph::new(Doubler {
+            other: other.clone(),
+            resolved: resolved.clone(),
+        });
+
+        assert_eq!(graph.query(0), 2);
+        assert_eq!(graph.query(1), 4);
+
+        // Only the other graph is invalidated, this one is just incremented.
+        inputs.values.lock()[0] = 10;
+        other.invalidate(0);
+        let other = other.increment(inputs);
+
+        resolved.lock().clear();
+
+        let graph = graph.increment(Doubler {
+            other,
+            resolved: resolved.clone(),
+        });
+
+        assert_eq!(graph.query(0), 20);
+        assert_eq!(graph.query(1), 4);
+        assert_eq!(*resolved.lock(), [0]);
+    }
+}
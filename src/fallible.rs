@@ -0,0 +1,236 @@
+use std::{
+    any::Any,
+    hash::Hash,
+    panic::{self, AssertUnwindSafe},
+    sync::Arc,
+};
+
+use rayon::prelude::{IntoParallelIterator, ParallelIterator};
+
+use crate::{Graph, QueryResolver, ResolveQuery};
+
+/// Like [`ResolveQuery`], but for resolvers whose queries can fail. Wrap it
+/// in [`Fallible`] to create a graph with it.
+///
+/// A failed query isn't cached: it unwinds (without running the panic hook)
+/// through every query depending on it, up to `Graph::try_query_all`, which
+/// returns the error. Those queries are left unresolved, so querying them
+/// again resolves them again, while the queries that succeeded stay cached.
+pub trait TryResolveQuery<Q, R>: Send + Sync {
+    type Error: Send + 'static;
+
+    fn try_resolve(&self, q: Q, resolve: Arc<QueryResolver<Q, R>>) -> Result<R, Self::Error>;
+
+    /// See `ResolveQuery::namespace`.
+    fn namespace(&self, _q: &Q) -> Option<String> {
+        None
+    }
+
+    /// See `ResolveQuery::is_input`.
+    fn is_input(&self, _q: &Q) -> bool {
+        false
+    }
+
+    /// See `ResolveQuery::is_volatile`.
+    fn is_volatile(&self, _q: &Q) -> bool {
+        false
+    }
+
+    /// See `ResolveQuery::recover_cycle`.
+    fn recover_cycle(&self, _q: &Q, _cycle: &[Q]) -> Option<R> {
+        None
+    }
+
+    /// See `ResolveQuery::placeholder`.
+    fn placeholder(&self, _q: &Q) -> Option<R> {
+        None
+    }
+}
+
+/// Adapts a [`TryResolveQuery`] to a [`ResolveQuery`].
+pub struct Fallible<T>(pub T);
+
+/// The payload a failed query unwinds with (the error is type erased, so that
+/// failures can be caught without knowing the error type of the resolver).
+pub(crate) struct QueryFailed(Box<dyn Any + Send>);
+
+impl QueryFailed {
+    /// Runs `f`, returning `None` if a query it makes fails. Any other panic is
+    /// resumed as is.
+    pub(crate) fn catch<T>(f: impl FnOnce() -> T) -> Option<T> {
+        match panic::catch_unwind(AssertUnwindSafe(f)) {
+            Ok(result) => Some(result),
+            Err(payload) => match payload.downcast::<QueryFailed>() {
+                Ok(_) => None,
+                Err(payload) => panic::resume_unwind(payload),
+            },
+        }
+    }
+}
+
+impl<Q, R, T: TryResolveQuery<Q, R>> ResolveQuery<Q, R> for Fallible<T> {
+    fn resolve(&self, q: Q, resolve: Arc<QueryResolver<Q, R>>) -> R {
+        match self.0.try_resolve(q, resolve) {
+            Ok(result) => result,
+            Err(error) => panic::resume_unwind(Box::new(QueryFailed(Box::new(error)))),
+        }
+    }
+
+    fn namespace(&self, q: &Q) -> Option<String> {
+        self.0.namespace(q)
+    }
+
+    fn is_input(&self, q: &Q) -> bool {
+        self.0.is_input(q)
+    }
+
+    fn is_volatile(&self, q: &Q) -> bool {
+        self.0.is_volatile(q)
+    }
+
+    fn recover_cycle(&self, q: &Q, cycle: &[Q]) -> Option<R> {
+        self.0.recover_cycle(q, cycle)
+    }
+
+    fn placeholder(&self, q: &Q) -> Option<R> {
+        self.0.placeholder(q)
+    }
+}
+
+impl<Q: Clone + Eq + Hash + Send + Sync, R: Clone + Send + Sync> QueryResolver<Q, R> {
+    /// Like `query`, but returns `None` if the query fails (with a
+    /// [`Fallible`] resolver) instead of failing the query being resolved too,
+    /// which is useful for dependencies that may not exist (like an optional
+    /// file).
+    ///
+    /// The dependency is recorded either way, so once the query succeeds (or
+    /// its result changes) the query being resolved is resolved again. Failed
+    /// queries aren't cached, so a dependency that keeps failing is resolved
+    /// again every time it's validated.
+    pub fn query_optional(&self, q: Q) -> Option<R> {
+        let result = QueryFailed::catch(|| self.query_internal(q.clone()));
+
+        if result.is_none() {
+            self.edges_from.lock().insert(q);
+        }
+
+        result.map(|result| result.as_ref().clone())
+    }
+}
+
+impl<Q: Clone + Eq + Hash + Send + Sync, R: Clone + Send + Sync> Graph<Q, R> {
+    /// Resolves every query (in parallel) with a [`Fallible`] resolver and
+    /// returns their results in the same order, so a batch where some queries
+    /// fail still returns the results of the others (e.g. to report every
+    /// error of a build, not just the first one). `E` must be the error type
+    /// of the resolver, any other panic is resumed as is.
+    pub fn try_query_all<E: Send + 'static>(
+        self: &Arc<Self>,
+        queries: impl IntoIterator<Item = Q>,
+    ) -> Vec<Result<R, E>> {
+        let queries = queries.into_iter().collect::<Vec<_>>();
+
+        queries.iter().for_each(|q| self.log_query(q));
+
+        queries
+            .into_par_iter()
+            .map(|q| {
+                panic::catch_unwind(AssertUnwindSafe(|| self.query_internal(q).as_ref().clone()))
+                    .map_err(|payload| match payload.downcast::<QueryFailed>() {
+                        Ok(failed) => match failed.0.downcast::<E>() {
+                            Ok(error) => *error,
+                            Err(error) => panic::resume_unwind(Box::new(QueryFailed(error))),
+                        },
+                        Err(payload) => panic::resume_unwind(payload),
+                    })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+    enum Query {
+        Value(u32),
+        Missing,
+        Clock,
+    }
+
+    /// Counts how many times each kind of query is resolved (across
+    /// iterations, since every iteration gets a resolver with the same
+    /// counters).
+    #[derive(Clone, Default)]
+    struct Resolver {
+        values: Arc<AtomicUsize>,
+        missing: Arc<AtomicUsize>,
+        clock: Arc<AtomicUsize>,
+    }
+
+    impl TryResolveQuery<Query, u32> for Resolver {
+        type Error = String;
+
+        fn try_resolve(
+            &self,
+            q: Query,
+            _resolve: Arc<QueryResolver<Query, u32>>,
+        ) -> Result<u32, String> {
+            match q {
+                Query::Value(n) => {
+                    self.values.fetch_add(1, Ordering::Relaxed);
+                    Ok(n)
+                }
+                Query::Missing => {
+                    self.missing.fetch_add(1, Ordering::Relaxed);
+                    Err("missing".to_string())
+                }
+                Query::Clock => Ok(self.clock.fetch_add(1, Ordering::Relaxed) as u32),
+            }
+        }
+
+        fn is_input(&self, q: &Query) -> bool {
+            matches!(q, Query::Value(_))
+        }
+
+        fn is_volatile(&self, q: &Query) -> bool {
+            *q == Query::Clock
+        }
+    }
+
+    #[test]
+    fn failures_are_reported_without_being_cached() {
+        let resolver = Resolver::default();
+        let graph = Graph::new(Fallible(resolver.clone()));
+        let queries = [Query::Value(1), Query::Missing, Query::Value(2)];
+
+        for _ in 0..2 {
+            let results = graph.try_query_all::<String>(queries.clone());
+            assert_eq!(results, [Ok(1), Err("missing".to_string()), Ok(2)]);
+        }
+
+        // The successes were cached, the failure is resolved every time.
+        assert_eq!(resolver.values.load(Ordering::Relaxed), 2);
+        assert_eq!(resolver.missing.load(Ordering::Relaxed), 2);
+    }
+
+    #[test]
+    fn hooks_are_forwarded() {
+        let resolver = Resolver::default();
+        let graph = Graph::new(Fallible(resolver.clone()));
+
+        assert_eq!(graph.query(Query::Clock), 0);
+        graph.query(Query::Value(1));
+
+        // The volatile query is resolved again in the next iteration, while the
+        // input is reused.
+        let graph = graph.increment(Fallible(resolver.clone()));
+
+        assert_eq!(graph.query(Query::Clock), 1);
+        graph.query(Query::Value(1));
+        assert_eq!(resolver.values.load(Ordering::Relaxed), 1);
+    }
+}
@@ -1,15 +1,104 @@
 use std::{
-    cell::RefCell,
+    collections::{BTreeMap, HashMap as StdHashMap, HashSet as StdHashSet, VecDeque},
     fmt::Debug,
-    hash::Hash,
-    sync::{Arc, OnceLock},
+    hash::{DefaultHasher, Hash, Hasher},
+    ops::Deref,
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc, OnceLock,
+    },
+    thread,
+    time::{Duration, Instant},
 };
 
-use hashbrown::HashSet;
-use map::ConcurrentMap;
-use rayon::prelude::{IntoParallelRefIterator, ParallelIterator};
+use builder::Config;
+use compress::Stored;
+use cycle::Path;
+use external::{ExternalEdges, Lineage};
+use fallible::QueryFailed;
+use hashbrown::{HashMap, HashSet};
+use namespace::NamespaceIndex;
+use parking_lot::{Mutex, RwLock};
+use part::Parts;
+use pin::Pinned;
+use projection::Projections;
+use rayon::prelude::{IntoParallelIterator, IntoParallelRefIterator, ParallelIterator};
+use stats::StatsCounters;
+use store::NodeMap;
+use subscribe::Subscribers;
 
+pub use budget::BudgetExceeded;
+pub use builder::{GraphBuilder, ValidationStrategy};
+pub use cancel::Cancelled;
+pub use change::{BitEq, BitEquality, ChangeDetector, ChangePipeline, ChangePolicy, Equality};
+pub use compress::Compressor;
+pub use cycle::QueryCycle;
+pub use explain::Explanation;
+pub use ext::QueryResultExt;
+pub use fallible::{Fallible, TryResolveQuery};
+pub use local::{LocalGraph, LocalQueryResolver, LocalResolveQuery};
+pub use log::{replay, QueryLog};
+#[cfg(feature = "contention_stats")]
+pub use map::ContentionStats;
+pub use map::{ConcurrentMap, Entry, NodeStore, NodeStoreFamily};
+pub use memo::Memo;
+pub use named::NamedQuery;
+#[cfg(feature = "bincode")]
+pub use persist::SnapshotFileError;
+pub use progress::ProgressSink;
+pub use purity::PurityViolation;
+pub use query::Query;
+#[cfg(feature = "derive")]
+pub use query_graph_derive::Query;
+pub use snapshot::Snapshot;
+pub use staging::StagedIncrement;
+pub use stats::{IncrementPlan, IncrementalityWarning, Resolution, Stats, TopologyStats};
+#[cfg(feature = "testing")]
+pub use testing::assert_incremental_consistency;
+pub use verify::ImpurityReport;
+
+#[cfg(feature = "access_stats")]
+mod access;
+mod budget;
+mod builder;
+mod cancel;
+mod change;
+mod compress;
+mod context;
+mod cycle;
+mod explain;
+mod ext;
+mod external;
+mod fallible;
+mod local;
+mod log;
+mod lru;
 mod map;
+mod memo;
+mod named;
+mod namespace;
+mod part;
+#[cfg(feature = "bincode")]
+mod persist;
+mod pin;
+mod pool;
+mod progress;
+mod projection;
+mod purity;
+mod query;
+mod redact;
+mod resume;
+#[cfg(feature = "debug")]
+mod slow_clone;
+mod snapshot;
+mod staging;
+mod stats;
+mod store;
+mod subscribe;
+#[cfg(feature = "testing")]
+mod testing;
+mod verify;
 
 /// The `Graph` struct represents a concurrent query dependency graph. It provides
 /// the infrastructure for managing, resolving, and optimizing a wide range of
@@ -80,60 +169,955 @@ pub struct Graph<Q, R> {
     /// It's just a reference to the map from the previous iteration and
     /// so is very efficient.
     old: QueryNodeMap<Q, R>,
+    /// The new map of the iteration this one is a scratch layer on top of (see
+    /// `Graph::query_with_budget`), whose resolved nodes are reused as they are.
+    base: Option<QueryNodeMap<Q, R>>,
     /// The resolver used to resolve queries. The resolver can have its
     /// own state as long as it's Sync + Send.
-    resolver: Box<dyn ResolveQuery<Q, R>>,
+    resolver: Arc<dyn ResolveQuery<Q, R>>,
+    /// The configuration from the `GraphBuilder`, shared by every iteration.
+    config: Arc<Config<Q, R>>,
+    /// The log of top-level queries (if enabled). It's shared by every
+    /// iteration incremented from the graph it was enabled on.
+    log: Option<Arc<Mutex<QueryLog<Q>>>>,
+    /// The number of queries resolved in this iteration so far.
+    resolved: AtomicUsize,
+    /// How the queries in this iteration were resolved.
+    stats: StatsCounters,
+    /// The number of queries being resolved right now.
+    in_flight: AtomicUsize,
+    /// Whether this iteration was cancelled.
+    cancelled: AtomicBool,
+    /// The clock nodes are stamped with when accessed (for LRU eviction).
+    clock: AtomicU64,
+    /// When this iteration was created (access times are relative to it).
+    #[cfg(feature = "access_stats")]
+    created: Instant,
+    /// Held by the thread evicting nodes (for LRU eviction).
+    evicting: Mutex<()>,
+    /// The impurities found in this iteration (in verification mode).
+    impurity_reports: Mutex<Vec<ImpurityReport>>,
+    /// Shared by every iteration incremented from the same graph.
+    lineage: Lineage<Q, R>,
+    /// The cached projections of results (see `derive`).
+    projections: Projections<Q, R>,
+    /// The subscribers to changed results (see `subscribe`).
+    subscribers: Subscribers<Q, R>,
+    /// The queries that are never evicted (see `pin`).
+    pinned: Pinned<Q>,
+    /// The queries invalidated in this iteration, mapped to whether they were
+    /// already resolved when they were invalidated (so they're still stale).
+    invalidated: RwLock<HashMap<Q, bool>>,
+    /// The queries resolved in each namespace in this iteration.
+    namespaces: NamespaceIndex<Q>,
+    /// The queries resolved in each namespace in the previous iteration.
+    old_namespaces: NamespaceIndex<Q>,
+    /// Bumped whenever a node is stored in (or dropped from) this iteration,
+    /// which invalidates the cached reverse edges. The cache is only valid if
+    /// the generation is exactly the one it was built at, so a wrapped counter
+    /// could make a stale cache look valid. It's a `u64` (even where `usize` is
+    /// 32 bits) so it effectively never wraps: at a billion nodes stored per
+    /// second it would take centuries.
+    generation: AtomicU64,
+    /// The reverse edges, cached along with the generation they were built at.
+    reverse_edges: Mutex<Option<ReverseEdges<Q>>>,
+    /// The queries resolved in this iteration, in the order they finished.
+    #[cfg(feature = "debug")]
+    resolution_order: Mutex<Vec<Q>>,
+}
+
+/// The state shared by every iteration incremented from the same graph.
+struct Shared<Q, R> {
+    log: Option<Arc<Mutex<QueryLog<Q>>>>,
+    lineage: Lineage<Q, R>,
+    projections: Projections<Q, R>,
+    subscribers: Subscribers<Q, R>,
+    pinned: Pinned<Q>,
+}
+
+impl<Q: Eq + Hash, R> Default for Shared<Q, R> {
+    fn default() -> Self {
+        Self {
+            log: None,
+            lineage: Default::default(),
+            projections: Default::default(),
+            subscribers: Default::default(),
+            pinned: Default::default(),
+        }
+    }
 }
 
 #[derive(Debug)]
 struct Node<Q, R> {
-    result: R,
+    /// The result is reference counted (or compressed) so that reusing an old
+    /// result (and handing out a `QueryRef`) never requires `R: Clone`.
+    result: Stored<R>,
     changed: bool,
     edges_from: Arc<HashSet<Q>>,
+    /// The queries on other graphs this node depends on.
+    external_edges: ExternalEdges,
+    /// The dependencies this node only depends on parts of.
+    parts: Parts<Q, R>,
+    resolution: Resolution,
+    /// The number of times the resolver resolved the query, across every
+    /// iteration (see `Graph::hot_queries`).
+    resolutions: u64,
+}
+
+/// The once-cell each node is stored in. Resolving a node initializes its cell,
+/// and any other thread querying the node while it's being resolved blocks on
+/// the cell until it's initialized.
+///
+/// The backend is selected with cargo features:
+///
+/// - `std::sync::OnceLock` (the default) has no extra dependencies.
+///
+/// - `once_cell::sync::OnceCell` (the `once_cell` feature) behaves the same,
+///   but lets the graph share the implementation with the rest of a dependency
+///   tree that already uses `once_cell`.
+///
+/// Both backends block. An awaiting backend (like `tokio::sync::OnceCell`)
+/// isn't offered because resolvers are synchronous, so a resolution can't be
+/// awaited without blocking a thread anyway.
+#[cfg(not(feature = "once_cell"))]
+type NodeCell<T> = std::sync::OnceLock<T>;
+#[cfg(feature = "once_cell")]
+type NodeCell<T> = once_cell::sync::OnceCell<T>;
+
+type ReverseEdges<Q> = (u64, Arc<HashMap<Q, Vec<Q>>>);
+
+/// The slot a node is stored in: its cell, along with whether it's being
+/// resolved right now (which is only needed to detect cycles cheaply) and when
+/// it was last accessed.
+#[derive(Debug)]
+struct Slot<Q, R> {
+    cell: NodeCell<Node<Q, R>>,
+    resolving: AtomicBool,
+    /// The tick of the last access to the node (for LRU eviction).
+    accessed: AtomicU64,
+    /// The hash of the result, computed the first time it's requested.
+    result_hash: OnceLock<u64>,
+    /// The progress of validating the node, if it was interrupted by
+    /// cancellation (see `resume::Interruption`).
+    partial: Mutex<Option<resume::PartialValidation<Q, R>>>,
+    #[cfg(feature = "access_stats")]
+    access: access::AccessCounters,
+}
+
+impl<Q, R> Slot<Q, R> {
+    fn new() -> Self {
+        Self {
+            cell: NodeCell::new(),
+            resolving: AtomicBool::new(false),
+            accessed: AtomicU64::new(0),
+            result_hash: OnceLock::new(),
+            partial: Mutex::new(None),
+            #[cfg(feature = "access_stats")]
+            access: Default::default(),
+        }
+    }
+
+    fn resolved(node: Node<Q, R>) -> Self {
+        Self {
+            cell: NodeCell::from(node),
+            resolving: AtomicBool::new(false),
+            accessed: AtomicU64::new(0),
+            result_hash: OnceLock::new(),
+            partial: Mutex::new(None),
+            #[cfg(feature = "access_stats")]
+            access: Default::default(),
+        }
+    }
+
+    /// Initializes the cell with `resolve` if it isn't initialized yet, marking
+    /// the slot as resolving meanwhile.
+    ///
+    /// If `resolve` panics the cell is left uninitialized, and the slot stops
+    /// resolving once the panic unwinds past the guard, so the node is resolved
+    /// again by a later query (and a thread that was waiting on the cell takes
+    /// over resolving it).
+    fn resolve_with(&self, resolve: impl FnOnce() -> Node<Q, R>) -> &Node<Q, R> {
+        self.cell.get_or_init(|| {
+            self.resolving.store(true, Ordering::Release);
+            let _resolving = Resolving(&self.resolving);
+
+            resolve()
+        })
+    }
+}
+
+impl<Q, R> Deref for Slot<Q, R> {
+    type Target = NodeCell<Node<Q, R>>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.cell
+    }
 }
 
-type QueryNodeMap<Q, R> = Arc<ConcurrentMap<Q, Arc<OnceLock<Node<Q, R>>>>>;
+type NodeRef<Q, R> = Arc<Slot<Q, R>>;
 
-impl<Q: Debug + Clone + Eq + Hash, R: Debug + Clone> Debug for Graph<Q, R> {
+type QueryNodeMap<Q, R> = Arc<NodeMap<Q, NodeRef<Q, R>>>;
+
+impl<Q: Debug + Clone + Eq + Hash, R: Debug> Debug for Graph<Q, R> {
+    /// Shows the nodes of both maps, with their queries and results redacted
+    /// (see `GraphBuilder::redact_queries`).
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("Graph")
-            .field("new", &self.new)
-            .field("old", &self.old)
+            .field("new", &self.debug_nodes(&self.new))
+            .field("old", &self.debug_nodes(&self.old))
             .finish()
     }
 }
 
-impl<Q: Clone + Eq + Hash + Send + Sync, R: Clone + Eq + Send + Sync> Graph<Q, R> {
+impl<Q: Clone + Eq + Hash + Send + Sync, R: PartialEq + Send + Sync> Graph<Q, R> {
     pub fn new(resolver: impl ResolveQuery<Q, R> + 'static) -> Arc<Self> {
-        Arc::new(Self {
-            new: Arc::new(ConcurrentMap::new()),
-            old: Arc::new(ConcurrentMap::new()),
-            resolver: Box::new(resolver),
-        })
+        GraphBuilder::new().build(resolver)
     }
 
-    pub fn query(self: &Arc<Self>, q: Q) -> R {
+    /// Creates a graph that records every top-level `query` (but not the
+    /// sub-queries made by resolvers) into a [`QueryLog`], which can later be
+    /// retrieved with `query_log` and replayed with [`replay`].
+    pub fn with_query_log(resolver: impl ResolveQuery<Q, R> + 'static) -> Arc<Self> {
+        GraphBuilder::new().query_log().build(resolver)
+    }
+
+    /// Creates a graph where the stubbed queries already have the given
+    /// results, which makes it easy to test a resolver in isolation: querying
+    /// the query under test resolves it with the resolver, while the queries
+    /// it depends on return the canned results (and are still recorded as its
+    /// dependencies). Queries that aren't stubbed are resolved normally.
+    pub fn with_stubbed(
+        resolver: impl ResolveQuery<Q, R> + 'static,
+        stubs: impl IntoIterator<Item = (Q, R)>,
+    ) -> Arc<Self> {
+        let graph = Self::new(resolver);
+
+        for (q, result) in stubs {
+            // A fresh graph has nothing resolved, so this only fails if the
+            // same query is stubbed twice (in which case the first stub wins).
+            let _ = graph.install(q, result, []);
+        }
+
+        graph
+    }
+
+    /// Creates a graph whose previous iteration consists of the given input
+    /// nodes, so the first iteration validates against them instead of
+    /// resolving them from scratch (like a graph built from a snapshot of just
+    /// the inputs). Inputs the resolver declares with `ResolveQuery::is_input`
+    /// are reused as is, while the others are resolved again and compared.
+    pub fn from_inputs(
+        resolver: impl ResolveQuery<Q, R> + 'static,
+        inputs: impl IntoIterator<Item = (Q, R)>,
+    ) -> Arc<Self> {
+        GraphBuilder::new().build_from_snapshot(inputs.into_iter().collect(), resolver)
+    }
+}
+
+impl<R: PartialEq + Send + Sync + 'static> Graph<(), R> {
+    /// Creates a graph for a single (unit) query, like the "whole program"
+    /// query of a compiler, whose node is stored in a once-lock instead of a
+    /// map (see `GraphBuilder::singleton` and the `singleton` benchmark).
+    pub fn singleton(resolver: impl ResolveQuery<(), R> + 'static) -> Arc<Self> {
+        GraphBuilder::new().singleton().build(resolver)
+    }
+}
+
+impl<Q: Clone + Eq + Hash + Send + Sync, R: Send + Sync> Graph<Q, R> {
+    /// Returns a copy of the query log, or `None` if the graph wasn't created
+    /// with `with_query_log`.
+    pub fn query_log(&self) -> Option<QueryLog<Q>> {
+        self.log.as_ref().map(|log| log.lock().clone())
+    }
+
+    /// Resolves the query and returns a handle to the result stored in the
+    /// graph instead of cloning it (see [`QueryRef`]). This is the only way to
+    /// query a graph whose results don't implement `Clone`.
+    pub fn query_ref(self: &Arc<Self>, q: Q) -> QueryRef<R> {
+        self.log_query(&q);
+        QueryRef::new(self.query_internal(q))
+    }
+
+    /// Resolves the query with the resolver without caching its result, so
+    /// every call resolves it again (e.g. for output that shouldn't be reused).
+    /// The queries it depends on are resolved (and cached, along with their
+    /// own dependencies) normally.
+    pub fn query_uncached(self: &Arc<Self>, q: Q) -> R {
+        self.log_query(&q);
+        self.check_cancelled();
+
+        let path = cycle::push(&Path::default(), q.clone());
+        let resolver = Arc::new(QueryResolver::new(self.clone(), path));
+        self.resolver.resolve(q, resolver)
+    }
+
+    /// Starts resolving the queries in the background on the rayon thread pool
+    /// and returns immediately. Later calls to `query` will find the results
+    /// already resolved (or wait for the in-flight resolution to finish, it's
+    /// never resolved twice).
+    ///
+    /// Prefetched queries aren't recorded in the query log. If a prefetched
+    /// query panics, the panic is discarded and the query will be resolved
+    /// again (and panic again) when it's actually queried.
+    pub fn prefetch(self: &Arc<Self>, queries: impl IntoIterator<Item = Q>)
+    where
+        Q: 'static,
+        R: 'static,
+    {
+        for q in queries {
+            let graph = self.clone();
+
+            rayon::spawn(move || {
+                let _ = panic::catch_unwind(AssertUnwindSafe(|| graph.query_internal(q)));
+            });
+        }
+    }
+
+    /// Maps the result of every query resolved in this iteration so far
+    /// through `f`, returning the mapped results keyed by query. This is a
+    /// snapshot, later resolutions aren't reflected in it.
+    pub fn map_results<R2>(&self, mut f: impl FnMut(&R) -> R2) -> StdHashMap<Q, R2> {
+        self.new
+            .iter()
+            .filter_map(|(q, node)| {
+                let node = node.get()?;
+                Some((q, f(&self.load(&node.result))))
+            })
+            .collect()
+    }
+
+    /// Calls `f` with every query resolved in this iteration so far whose
+    /// result changed since the previous iteration, along with its result.
+    /// Queries that are new in this iteration (with no old result to compare
+    /// against) aren't considered changed.
+    pub fn for_each_changed(&self, mut f: impl FnMut(&Q, &R)) {
+        for (q, slot) in self.new.iter() {
+            if let Some(node) = slot.get() {
+                if node.changed {
+                    f(&q, &self.load(&node.result));
+                }
+            }
+        }
+    }
+
+    /// Calls `f` (in parallel) with every query resolved in this iteration so
+    /// far, along with its result, like to write every artifact of a build to
+    /// disk. No lock of the graph is held while `f` is called, so it can query
+    /// the graph too.
+    pub fn par_for_each_node(&self, f: impl Fn(&Q, &R) + Sync) {
+        self.new.par_iter().for_each(|(q, slot)| {
+            if let Some(node) = slot.get() {
+                f(&q, &self.load(&node.result));
+            }
+        });
+    }
+
+    /// Returns a hash of the query's result, or `None` if it isn't resolved in
+    /// this iteration. The hash is computed once per node and cached, so it's
+    /// cheap to request repeatedly, and no result is ever cloned.
+    ///
+    /// The hash is computed with `DefaultHasher::new`, so it's stable across
+    /// runs (and so can key external artifacts), but not necessarily across
+    /// Rust versions.
+    pub fn query_result_hash(&self, q: &Q) -> Option<u64>
+    where
+        R: Hash,
+    {
+        let slot = self.new.get(q)?;
+        let node = slot.get()?;
+
+        Some(*slot.result_hash.get_or_init(|| {
+            let mut hasher = DefaultHasher::new();
+            self.load(&node.result).hash(&mut hasher);
+            hasher.finish()
+        }))
+    }
+
+    /// Returns whether the query's result equals `result` (e.g. a result held
+    /// on to from a previous iteration), or `None` if it isn't resolved in this
+    /// iteration. The cached result is compared in place, so it's never cloned
+    /// (though a compressed result still has to be decompressed).
+    pub fn query_result_eq(&self, q: &Q, result: &R) -> Option<bool>
+    where
+        R: PartialEq,
+    {
+        let node = self.new.get(q)?;
+        let node = node.get()?;
+
+        Some(*self.load(&node.result) == *result)
+    }
+
+    /// Returns the number of queries being resolved right now. A query that's
+    /// waiting on its dependencies is still in flight, so with deep chains of
+    /// dependencies this can be much higher than the number of threads.
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::Relaxed)
+    }
+
+    /// Returns the queries resolved in this iteration, in the order they
+    /// finished resolving (so dependencies come before their dependents).
+    /// Queries resolved in parallel are interleaved in whatever order they
+    /// happened to finish, so this is only a best-effort picture of what ran.
+    #[cfg(feature = "debug")]
+    pub fn resolution_order(&self) -> Vec<Q> {
+        self.resolution_order.lock().clone()
+    }
+
+    /// Resolves the roots (in parallel) without returning their results. This
+    /// drives the revalidation of everything the roots depend on, warming the
+    /// graph, and returns every query reachable from the roots whose result
+    /// changed in this iteration.
+    pub fn validate_all(self: &Arc<Self>, roots: impl IntoIterator<Item = Q>) -> StdHashSet<Q> {
+        let roots = roots.into_iter().collect::<Vec<_>>();
+
+        roots.par_iter().for_each(|q| {
+            self.query_internal(q.clone());
+        });
+
+        let mut changed = StdHashSet::new();
+
+        self.visit_resolved(roots, |q, node| {
+            if node.changed {
+                changed.insert(q.clone());
+            }
+        });
+
+        changed
+    }
+
+    /// Resolves the roots and every query they transitively depend on before
+    /// returning, so the graph is fully warm: querying any of them afterwards
+    /// never resolves anything. The queries are resolved level by level (the
+    /// roots, then their dependencies, and so on), each level in parallel.
+    ///
+    /// Returns the number of queries in the roots' dependency closure (all of
+    /// which are resolved when this returns).
+    pub fn resolve_to_completion(self: &Arc<Self>, roots: impl IntoIterator<Item = Q>) -> usize {
+        let mut visited = roots.into_iter().collect::<HashSet<_>>();
+        let mut level = visited.iter().cloned().collect::<Vec<_>>();
+
+        while !level.is_empty() {
+            let edges = level
+                .par_iter()
+                .map(|q| {
+                    let node = self.get_node(q);
+                    self.get_or_resolve(&node, q.clone(), &Path::default())
+                        .edges_from
+                        .clone()
+                })
+                .collect::<Vec<_>>();
+
+            level = edges
+                .iter()
+                .flat_map(|edges| edges.iter())
+                .filter(|q| visited.insert((*q).clone()))
+                .cloned()
+                .collect();
+        }
+
+        visited.len()
+    }
+
+    /// Drops every node (in both this iteration and the old one) whose query
+    /// doesn't match the predicate, calling the eviction callback for each of
+    /// them. Dropped queries are simply resolved again if they're queried.
+    /// Pinned queries (see `pin`) are always kept.
+    ///
+    /// Note that the old map is shared with the previous iteration, so its
+    /// nodes are dropped from the previous iteration as well.
+    pub fn retain(&self, mut pred: impl FnMut(&Q) -> bool) {
+        let mut evicted = HashSet::new();
+        let pinned = self.pinned.read();
+
+        for map in [&self.new, &self.old] {
+            map.retain(|q, _| {
+                let retain = pinned.contains(q) || pred(q);
+
+                if !retain {
+                    evicted.insert(q.clone());
+                }
+
+                retain
+            });
+        }
+
+        drop(pinned);
+        self.generation.fetch_add(1, Ordering::Release);
+        self.retain_projections(|q| !evicted.contains(q));
+
+        // The callback is only called after the shards are unlocked, so it's
+        // free to use the graph.
+        if let Some(on_evict) = &self.config.on_evict {
+            evicted.iter().for_each(on_evict);
+        }
+    }
+
+    /// Marks a query (typically an input, see `ResolveQuery::is_input`) as
+    /// changed, so that it's resolved again when it's validated in this
+    /// iteration instead of reusing its old result. Invalidating a query that
+    /// was already resolved in this iteration has no effect on this iteration,
+    /// but like any invalidation that wasn't applied yet it's carried over to
+    /// the next iteration when incrementing.
+    pub fn invalidate(&self, q: Q) {
+        self.mark_invalidated(&mut self.invalidated.write(), q);
+    }
+
+    pub(crate) fn mark_invalidated(&self, invalidated: &mut HashMap<Q, bool>, q: Q) {
+        let resolved = self.new.get(&q).is_some_and(|slot| slot.get().is_some());
+        *invalidated.entry(q).or_default() |= resolved;
+    }
+
+    /// Installs an already computed result for the query (e.g. one computed by
+    /// a peer in a distributed build) along with the queries it depends on, so
+    /// that querying it returns the result without resolving it. The result is
+    /// compared against the old result (if there is one) like it would've been
+    /// if the graph resolved it. Installing doesn't count as a resolution (see
+    /// `hot_queries`), but the count of the old node is kept.
+    ///
+    /// If the query was already resolved (or is resolved while installing) the
+    /// result isn't installed and is given back as the error. Callers that
+    /// don't care can just ignore it.
+    pub fn install(
+        &self,
+        q: Q,
+        result: R,
+        edges_from: impl IntoIterator<Item = Q>,
+    ) -> Result<(), R> {
+        let node = self.get_node(&q);
+        let mut result = Some(result);
+
+        node.get_or_init(|| {
+            let result = self.store(result.take().unwrap());
+            let old = self.old.get(&q);
+            let old_node = old.as_ref().and_then(|old| old.get());
+
+            Node {
+                changed: match (&old, old_node) {
+                    (Some(_), Some(old_node)) => self.changed(&old_node.result, &result),
+                    (Some(_), None) => true,
+                    (None, _) => false,
+                },
+                result,
+                edges_from: Arc::new(edges_from.into_iter().collect()),
+                external_edges: Arc::new([]),
+                parts: Default::default(),
+                resolution: Resolution::Fresh,
+                resolutions: old_node.map_or(0, |old_node| old_node.resolutions),
+            }
+        });
+
+        match result {
+            Some(result) => Err(result),
+            None => {
+                self.generation.fetch_add(1, Ordering::Release);
+
+                if let Some(node) = node.get() {
+                    self.notify_changed(&q, node);
+                }
+
+                Ok(())
+            }
+        }
+    }
+
+    /// Visits every resolved node reachable from the roots (including the roots)
+    /// exactly once, following the dependencies of each node. Nodes that aren't
+    /// resolved in this iteration (and so their dependencies) are skipped.
+    fn visit_resolved(
+        &self,
+        roots: impl IntoIterator<Item = Q>,
+        mut f: impl FnMut(&Q, &Node<Q, R>),
+    ) {
+        let mut visited = HashSet::new();
+        let mut stack = roots.into_iter().collect::<Vec<_>>();
+
+        while let Some(q) = stack.pop() {
+            if visited.contains(&q) {
+                continue;
+            }
+
+            if let Some(node) = self.new.get(&q) {
+                if let Some(node) = node.get() {
+                    f(&q, node);
+
+                    stack.extend(
+                        node.edges_from
+                            .iter()
+                            .filter(|parent| !visited.contains(*parent))
+                            .cloned(),
+                    );
+                }
+            }
+
+            visited.insert(q);
+        }
+    }
+
+    /// Whether the roots and everything they transitively depend on are
+    /// resolved in this iteration, i.e. whether querying any of them would
+    /// return without resolving (or revalidating) anything. Nodes can be left
+    /// out by queries that weren't made yet, installed results (whose
+    /// dependencies aren't resolved), or eviction.
+    pub fn is_fully_validated(&self, roots: impl IntoIterator<Item = Q>) -> bool {
+        let is_resolved = |q: &Q| self.new.get(q).is_some_and(|slot| slot.get().is_some());
+
+        let roots = roots.into_iter().collect::<Vec<_>>();
+        let mut validated = roots.iter().all(is_resolved);
+
+        self.visit_resolved(roots, |_, node| {
+            validated &= node.edges_from.iter().all(is_resolved);
+        });
+
+        validated
+    }
+
+    /// Returns every query transitively reachable from the roots (including the
+    /// roots) by following the dependencies of the nodes resolved in this
+    /// iteration, i.e. the queries that still matter for the roots. Everything
+    /// else could be dropped (see `retain`) without affecting them.
+    ///
+    /// Dependencies that aren't resolved in this iteration are included, but
+    /// their own dependencies aren't known, so they aren't followed. Neither are
+    /// unresolved roots (which are only included if they're resolved).
+    pub fn reachable_from(&self, roots: impl IntoIterator<Item = Q>) -> StdHashSet<Q> {
+        let mut reachable = StdHashSet::new();
+
+        self.visit_resolved(roots, |q, node| {
+            reachable.insert(q.clone());
+            reachable.extend(node.edges_from.iter().cloned());
+        });
+
+        reachable
+    }
+
+    /// Returns every query in this iteration that transitively depends on
+    /// `input` (including `input` itself if it was resolved), which is every
+    /// query that would have to be resolved again if `input` changed.
+    ///
+    /// This only knows about the dependencies of queries resolved so far, and
+    /// the reverse index it's computed with is cached until another query is
+    /// resolved (or installed), so repeated calls are cheap.
+    pub fn queries_depending_on(&self, input: &Q) -> StdHashSet<Q> {
+        let mut affected = Self::transitive_dependents(&self.reverse_edges(), [input.clone()]);
+
+        if self.new.get(input).is_none_or(|node| node.get().is_none()) {
+            affected.remove(input);
+        }
+
+        affected
+    }
+
+    /// Returns the dependency structure of this iteration so far: every
+    /// resolved query along with the queries it depends on, without any
+    /// results. This is much lighter than a snapshot, and is meant for
+    /// external graph analysis tools.
+    pub fn export_edges(&self) -> Vec<(Q, Vec<Q>)> {
+        self.new
+            .iter()
+            .filter_map(|(q, slot)| {
+                let node = slot.get()?;
+                Some((q, node.edges_from.iter().cloned().collect()))
+            })
+            .collect()
+    }
+
+    /// Returns the queries the query depended on when it was resolved in this
+    /// iteration, or `None` if it wasn't resolved (yet). Queries on other
+    /// graphs aren't included.
+    ///
+    /// This is meant for testing resolvers: a resolver that reads some state
+    /// directly instead of querying it silently loses the dependency (and
+    /// its result is reused even when the state changes), which is caught by
+    /// asserting the exact edges.
+    ///
+    /// ```ignore
+    /// let graph = Graph::new(MyResolver);
+    /// graph.query(Query::Typecheck(file));
+    ///
+    /// assert_eq!(
+    ///     graph.edges_of(&Query::Typecheck(file)),
+    ///     Some(HashSet::from([Query::Parse(file), Query::Prelude])),
+    /// );
+    /// ```
+    pub fn edges_of(&self, q: &Q) -> Option<StdHashSet<Q>> {
+        let node = self.new.get(q)?;
+        let node = node.get()?;
+        Some(node.edges_from.iter().cloned().collect())
+    }
+
+    /// Finds the queries (including the starting queries) that transitively
+    /// depend on the starting queries with a BFS over the reverse edges.
+    fn transitive_dependents(
+        dependents: &HashMap<Q, Vec<Q>>,
+        start: impl IntoIterator<Item = Q>,
+    ) -> StdHashSet<Q> {
+        let mut visited = start.into_iter().collect::<StdHashSet<_>>();
+        let mut queue = visited.iter().cloned().collect::<VecDeque<_>>();
+
+        while let Some(q) = queue.pop_front() {
+            for dependent in dependents.get(&q).into_iter().flatten() {
+                if visited.insert(dependent.clone()) {
+                    queue.push_back(dependent.clone());
+                }
+            }
+        }
+
+        visited
+    }
+
+    /// Gets the reverse of the dependency edges of every resolved node in this
+    /// iteration, building it again only if the nodes changed since it was
+    /// last built.
+    fn reverse_edges(&self) -> Arc<HashMap<Q, Vec<Q>>> {
+        // The generation is loaded before building, so a node stored while
+        // building invalidates the index right away.
+        let generation = self.generation.load(Ordering::Acquire);
+
+        if let Some((built, dependents)) = &*self.reverse_edges.lock() {
+            if *built == generation {
+                return dependents.clone();
+            }
+        }
+
+        let dependents = Arc::new(self.build_reverse_edges());
+        *self.reverse_edges.lock() = Some((generation, dependents.clone()));
+        dependents
+    }
+
+    /// Builds the reverse of the dependency edges of every resolved node in
+    /// this iteration, mapping each query to the queries that depend on it.
+    fn build_reverse_edges(&self) -> HashMap<Q, Vec<Q>> {
+        let mut dependents = HashMap::<Q, Vec<Q>>::new();
+
+        for (q, node) in self.new.iter() {
+            if let Some(node) = node.get() {
+                for parent in node.edges_from.iter() {
+                    dependents
+                        .entry(parent.clone())
+                        .or_default()
+                        .push(q.clone());
+                }
+            }
+        }
+
+        dependents
+    }
+
+    fn log_query(&self, q: &Q) {
+        if let Some(log) = &self.log {
+            log.lock().push(q.clone());
+        }
+    }
+
+    fn query_internal(self: &Arc<Self>, q: Q) -> Arc<R> {
+        self.query_on(q, &Path::default())
+    }
+
+    /// Queries the query on behalf of the resolution at the end of the path.
+    fn query_on(self: &Arc<Self>, q: Q, path: &Path<Q>) -> Arc<R> {
         let node = self.get_node(&q);
-        let node = node.get_or_init(|| self.resolve(q));
-        node.result.clone()
+        let node = self.get_or_resolve(&node, q, path);
+        self.load(&node.result)
+    }
+
+    /// Like `query_on`, but if resolving the query would close a cycle the
+    /// resolver gets to recover from it (see `ResolveQuery::recover_cycle`),
+    /// returning whether the result was recovered.
+    fn query_or_recover(self: &Arc<Self>, q: Q, path: &Path<Q>) -> (Arc<R>, bool) {
+        let slot = self.get_node(&q);
+
+        match self.get_or_recover(&slot, q, path, true) {
+            Ok(node) => (self.load(&node.result), false),
+            Err(recovered) => (recovered, true),
+        }
+    }
+
+    /// Gets the node from its slot, resolving it first if it isn't resolved.
+    fn get_or_resolve<'n>(
+        self: &Arc<Self>,
+        slot: &'n Slot<Q, R>,
+        q: Q,
+        path: &Path<Q>,
+    ) -> &'n Node<Q, R> {
+        match self.get_or_recover(slot, q, path, false) {
+            Ok(node) => node,
+            Err(_) => unreachable!("cycles are only recovered from when asked to"),
+        }
+    }
+
+    /// Like `get_or_resolve`, but with `recover` a cycle the resolver recovers
+    /// from gives back the recovered result instead of panicking.
+    fn get_or_recover<'n>(
+        self: &Arc<Self>,
+        slot: &'n Slot<Q, R>,
+        q: Q,
+        path: &Path<Q>,
+        recover: bool,
+    ) -> Result<&'n Node<Q, R>, Arc<R>> {
+        self.touch(slot);
+
+        if let Some(node) = slot.get() {
+            return Ok(node);
+        }
+
+        // Only a node that's being resolved can be on a cycle, so the path is
+        // only walked when this resolution would have to wait for it.
+        if slot.resolving.load(Ordering::Acquire) {
+            if let Some(recovered) = self.check_cycle(path, &q, recover) {
+                return Err(recovered);
+            }
+        }
+
+        let mut resolved = false;
+
+        let node = slot.resolve_with(|| {
+            resolved = true;
+            self.resolve(q, slot, path)
+        });
+
+        // The generation is only bumped once the node is stored, so that the
+        // reverse edges can't be cached without it.
+        if resolved {
+            self.generation.fetch_add(1, Ordering::Release);
+            self.evict_lru();
+        }
+
+        Ok(node)
+    }
+
+    /// Resolves the query without caching it, letting its dependencies recurse
+    /// `depth` more levels before they're replaced by placeholders. Queries
+    /// already resolved in this iteration are reused as is.
+    fn resolve_shallow(self: &Arc<Self>, q: Q, depth: usize) -> Arc<R> {
+        if let Some(result) = self.resolved_result(&q) {
+            return result;
+        }
+
+        let resolver = Arc::new(QueryResolver::shallow(self.clone(), depth));
+        Arc::new(self.resolver.resolve(q, resolver))
+    }
+
+    /// Gets the result of the query if it was already resolved in this
+    /// iteration, without resolving it.
+    fn resolved_result(&self, q: &Q) -> Option<Arc<R>> {
+        let node = self.new.get(q)?;
+        let node = node.get()?;
+        Some(self.load(&node.result))
     }
 
-    fn get_node(self: &Arc<Self>, q: &Q) -> Arc<OnceLock<Node<Q, R>>> {
+    fn get_node(&self, q: &Q) -> NodeRef<Q, R> {
+        if let Some(slot) = self.base.as_ref().and_then(|base| base.get(q)) {
+            if slot.get().is_some() {
+                return slot;
+            }
+        }
+
         self.new
-            .get_or_insert(q.clone(), || Arc::new(OnceLock::default()))
+            .get_or_insert_with_key(q, |_| Arc::new(Slot::new()))
+    }
+
+    fn resolve(self: &Arc<Self>, q: Q, slot: &Slot<Q, R>, path: &Path<Q>) -> Node<Q, R> {
+        #[cfg(feature = "debug")]
+        let resolving = q.clone();
+
+        let path = cycle::push(path, q.clone());
+
+        self.check_cancelled();
+        self.record_namespace(&q);
+
+        let observed =
+            (self.has_subscribers() || self.config.on_resolve.is_some()).then(|| q.clone());
+
+        if let Some(budget) = &path.budget {
+            budget.check();
+        }
+
+        let in_flight = InFlight::enter(&self.in_flight);
+        let node = self.resolve_node(q, slot, &path);
+        drop(in_flight);
+
+        // The budget is spent before the node is stored, so the resolution
+        // that exceeds it isn't cached.
+        if let (Some(budget), Resolution::Fresh | Resolution::Recomputed) =
+            (&path.budget, node.resolution)
+        {
+            budget.spend();
+        }
+
+        if let Some(q) = observed {
+            self.notify_changed(&q, &node);
+
+            if let Some(on_resolve) = &self.config.on_resolve {
+                on_resolve(&q, node.resolution);
+            }
+        }
+
+        self.stats.record(node.resolution);
+
+        #[cfg(feature = "debug")]
+        self.resolution_order.lock().push(resolving);
+
+        let resolved = self.resolved.fetch_add(1, Ordering::Relaxed) + 1;
+
+        if let Some((sink, every)) = &self.config.progress {
+            if resolved.is_multiple_of(*every) {
+                sink.resolved(resolved);
+            }
+        }
+
+        node
+    }
+
+    /// Whether the query is an input that wasn't invalidated in this iteration.
+    fn is_valid_input(&self, q: &Q) -> bool {
+        self.resolver.is_input(q) && !self.is_invalidated(q) && !self.resolver.is_volatile(q)
+    }
+
+    fn is_invalidated(&self, q: &Q) -> bool {
+        self.invalidated.read().contains_key(q)
     }
 
-    fn resolve(self: &Arc<Self>, q: Q) -> Node<Q, R> {
+    fn resolve_node(self: &Arc<Self>, q: Q, slot: &Slot<Q, R>, path: &Path<Q>) -> Node<Q, R> {
         if let Some(old) = self.old.get(&q) {
+            // If the previous iteration was interrupted (cancelled) while it was
+            // validating the node, the validation is resumed against the node it
+            // was validating, which is older than the (unresolved) old node.
+            let resumed = self.interrupted_validation(&old);
+
             // Since there was an old node we have to validate it.
-            let old_node = old.get();
+            let (old, old_node) = match &resumed {
+                Some(partial) => (partial.old.clone(), partial.old.get()),
+                None => (old.clone(), old.get()),
+            };
 
             if let Some(old_node) = old_node {
-                if old_node.edges_from.len() == 0 {
+                let is_root = old_node.edges_from.is_empty() && old_node.external_edges.is_empty();
+
+                if is_root && resumed.is_none() && self.is_valid_input(&q) {
+                    // Inputs can't change unless they're invalidated, so the
+                    // old result is reused without resolving it again.
+                    self.verify(&q, path, old_node);
+
+                    Node {
+                        result: old_node.result.clone(),
+                        edges_from: old_node.edges_from.clone(),
+                        external_edges: old_node.external_edges.clone(),
+                        parts: old_node.parts.clone(),
+                        changed: false,
+                        resolution: Resolution::Reused,
+                        resolutions: old_node.resolutions,
+                    }
+                } else if is_root {
                     // Since the node had no dependencies (a root node) we must
                     // resolve it again to see if it changed.
-                    let resolver = Arc::new(QueryResolver::new(self.clone()));
-                    let result = self.resolver.resolve(q, resolver.clone());
+                    let resolver = Arc::new(QueryResolver::with_old(
+                        self.clone(),
+                        path.clone(),
+                        old_node,
+                    ));
+                    let result = self.store(self.resolver.resolve(q, resolver.clone()));
+                    let (edges_from, parts) = resolver.take_edges();
+                    let (result, changed) = self.settle(&old_node.result, result);
 
                     Node {
                         // This is very important and crucial to the whole system
@@ -141,23 +1125,88 @@ impl<Q: Clone + Eq + Hash + Send + Sync, R: Clone + Eq + Send + Sync> Graph<Q, R
                         // changed must be false. This prevents nodes from needlessly
                         // being resolved again when their old values can be used
                         // instead.
-                        changed: result != old_node.result,
+                        changed,
                         result,
-                        edges_from: Arc::new(resolver.edges_from.take()),
+                        edges_from,
+                        external_edges: resolver.take_external_edges(),
+                        parts,
+                        resolution: Resolution::Recomputed,
+                        resolutions: old_node.resolutions + 1,
                     }
                 } else {
-                    let any_changed = old_node.edges_from.par_iter().any(|parent| {
+                    // Every dependency is validated through its node in the new map,
+                    // so no matter how many dependents reach it (e.g. dense diamonds),
+                    // a dependency is validated at most once per iteration. Any other
+                    // dependents just wait for (or read) the initialized node.
+                    //
+                    // When resuming, only the dependencies confirmed unchanged
+                    // before the interruption can be validated through their
+                    // nodes in the new map (since those were validated against
+                    // the node's revision), the others are assumed to have changed.
+                    let interruption = resume::Interruption {
+                        graph: self,
+                        slot,
+                        old: &old,
+                        old_node,
+                        resumed: resumed.as_ref(),
+                    };
+
+                    let changed = |parent: &Q| {
+                        if resumed
+                            .as_ref()
+                            .is_some_and(|partial| !partial.confirmed.contains(parent))
+                        {
+                            return true;
+                        }
+
                         let node = self.get_node(parent);
-                        let node = node.get_or_init(|| self.resolve(parent.clone()));
 
-                        node.changed
-                    });
+                        // A dependency that fails is considered changed, so that
+                        // this query is resolved again and handles the failure
+                        // (see `QueryResolver::query_optional`).
+                        let Some(node) =
+                            QueryFailed::catch(|| self.get_or_resolve(&node, parent.clone(), path))
+                        else {
+                            return true;
+                        };
+
+                        self.dependency_changed(old_node, parent, node)
+                    };
 
-                    if any_changed {
+                    let edges = &old_node.edges_from;
+
+                    let any_changed =
+                        match (self.config.sequential, self.config.validation_strategy) {
+                            (false, ValidationStrategy::FirstChange) => {
+                                edges.par_iter().any(changed)
+                            }
+                            (false, ValidationStrategy::AllChecked) => edges
+                                .par_iter()
+                                .map(changed)
+                                .reduce(|| false, |a, b| a || b),
+                            (true, ValidationStrategy::FirstChange) => edges.iter().any(changed),
+                            (true, ValidationStrategy::AllChecked) => {
+                                edges.iter().filter(|parent| changed(parent)).count() > 0
+                            }
+                        };
+
+                    drop(interruption);
+
+                    let any_changed =
+                        any_changed || Self::any_external_changed(&old_node.external_edges);
+
+                    if any_changed || self.is_invalidated(&q) || self.resolver.is_volatile(&q) {
                         // Since at least one dependency of this query has changed
-                        // we have to resolve this query again.
-                        let resolver = Arc::new(QueryResolver::new(self.clone()));
-                        let result = self.resolver.resolve(q, resolver.clone());
+                        // (or it was invalidated, or is volatile) we have to
+                        // resolve this query again.
+                        let resolver = Arc::new(QueryResolver::with_old(
+                            self.clone(),
+                            path.clone(),
+                            old_node,
+                        ));
+                        let result = self.store(self.resolver.resolve(q, resolver.clone()));
+                        let (edges_from, parts) = resolver.take_edges();
+                        let (result, changed) = self.settle(&old_node.result, result);
 
                         Node {
                             // This is very important and crucial to the whole system
@@ -165,85 +1214,1206 @@ impl<Q: Clone + Eq + Hash + Send + Sync, R: Clone + Eq + Send + Sync> Graph<Q, R
                             // changed must be false. This prevents nodes from needlessly
                             // being resolved again when their old values can be used
                             // instead.
-                            changed: result != old_node.result,
+                            changed,
                             result,
-                            edges_from: Arc::new(resolver.edges_from.take()),
+                            edges_from,
+                            external_edges: resolver.take_external_edges(),
+                            parts,
+                            resolution: Resolution::Recomputed,
+                            resolutions: old_node.resolutions + 1,
                         }
                     } else {
                         // The old result is still valid so we just clone it.
+                        self.verify(&q, path, old_node);
+
                         Node {
                             result: old_node.result.clone(),
                             edges_from: old_node.edges_from.clone(),
+                            external_edges: old_node.external_edges.clone(),
+                            parts: old_node.parts.clone(),
                             changed: false,
+                            resolution: Resolution::Reused,
+                            resolutions: old_node.resolutions,
                         }
                     }
                 }
             } else {
                 // Since the old node is not resolved yet we will just resolve
                 // it from scratch.
-                let resolver = Arc::new(QueryResolver::new(self.clone()));
-                let result = self.resolver.resolve(q, resolver.clone());
+                let resolver = Arc::new(QueryResolver::new(self.clone(), path.clone()));
+                let result = self.store(self.resolver.resolve(q, resolver.clone()));
+                let (edges_from, parts) = resolver.take_edges();
+
+                // We need to check again if the old node is still unresolved. Because
+                // if it isn't we can set changed to old_result != result. Otherwise,
+                // we always set changed to true.
+                let old_node = self.await_old(&old);
+                let (result, changed) = match old_node {
+                    Some(old_node) => self.settle(&old_node.result, result),
+                    None => (result, true),
+                };
 
                 Node {
-                    // We need to check again if the old node is still unresolved. Because
-                    // if it isn't we can set changed to old_result != result. Otherwise,
-                    // we always set changed to true.
-                    changed: match old.get() {
-                        Some(old_node) => result != old_node.result,
-                        None => true,
-                    },
+                    changed,
                     result,
-                    edges_from: Arc::new(resolver.edges_from.take()),
+                    edges_from,
+                    external_edges: resolver.take_external_edges(),
+                    parts,
+                    resolution: Resolution::Fresh,
+                    resolutions: old_node.map_or(0, |old_node| old_node.resolutions) + 1,
                 }
             }
         } else {
             // Since the node isn't in the old map then the query is new and resolved
             // from scratch.
-            let resolver = Arc::new(QueryResolver::new(self.clone()));
-            let result = self.resolver.resolve(q, resolver.clone());
+            let resolver = Arc::new(QueryResolver::new(self.clone(), path.clone()));
+            let result = self.store(self.resolver.resolve(q, resolver.clone()));
+            let (edges_from, parts) = resolver.take_edges();
 
             Node {
                 result,
                 // Since this is a new node, changed is always false.
                 changed: false,
-                edges_from: Arc::new(resolver.edges_from.take()),
+                edges_from,
+                external_edges: resolver.take_external_edges(),
+                parts,
+                resolution: Resolution::Fresh,
+                resolutions: 1,
             }
         }
     }
 
-    pub fn increment(self: &Arc<Self>, resolver: impl ResolveQuery<Q, R> + 'static) -> Arc<Self> {
-        Arc::new(Self {
-            new: Arc::new(ConcurrentMap::new()),
-            old: self.new.clone(),
-            resolver: Box::new(resolver),
-        })
+    /// Whether the (resolved) node of a dependency of the old node changed, as
+    /// far as the old node is concerned (see `parts_changed`). A dependency
+    /// without an old node (since it failed, or was evicted) can't be
+    /// compared, so it's considered changed even though it's resolved as a
+    /// new query.
+    fn dependency_changed(&self, old_node: &Node<Q, R>, parent: &Q, node: &Node<Q, R>) -> bool {
+        let changed = node.changed
+            || (node.resolution == Resolution::Fresh && self.old.get(parent).is_none());
+        changed && self.parts_changed(&old_node.parts, parent, node)
     }
-}
 
-pub struct QueryResolver<Q, R> {
-    graph: Arc<Graph<Q, R>>,
-    edges_from: RefCell<HashSet<Q>>,
-}
+    /// Gets the old node if it's resolved. With `GraphBuilder::await_old_results`
+    /// this waits (up to the timeout) for the previous iteration to finish
+    /// resolving it, since an accurate comparison saves the dependents from
+    /// being resolved again.
+    fn await_old<'n>(&self, old: &'n Slot<Q, R>) -> Option<&'n Node<Q, R>> {
+        if let Some(old_node) = old.get() {
+            return Some(old_node);
+        }
 
-unsafe impl<Q, R> Send for QueryResolver<Q, R> {}
-unsafe impl<Q, R> Sync for QueryResolver<Q, R> {}
+        Self::await_node(old, self.config.await_old_results?)
+    }
 
-impl<Q: Clone + Eq + Hash + Send + Sync, R: Clone + Eq + Send + Sync> QueryResolver<Q, R> {
-    fn new(graph: Arc<Graph<Q, R>>) -> Self {
-        Self {
+    /// Waits (up to the timeout) for the slot's node to be resolved by some
+    /// other thread, without resolving it, polling with a backoff.
+    fn await_node(slot: &Slot<Q, R>, timeout: Duration) -> Option<&Node<Q, R>> {
+        let deadline = Instant::now() + timeout;
+        let mut backoff = Duration::from_micros(10);
+
+        loop {
+            if let Some(node) = slot.get() {
+                return Some(node);
+            }
+
+            if Instant::now() >= deadline {
+                return None;
+            }
+
+            thread::sleep(backoff.min(deadline.saturating_duration_since(Instant::now())));
+            backoff = (backoff * 2).min(Duration::from_millis(1));
+        }
+    }
+
+    fn changed(&self, old: &Stored<R>, new: &Stored<R>) -> bool {
+        match (old, new) {
+            // Deterministic compression means equal bytes are equal results.
+            (Stored::Compressed(old), Stored::Compressed(new)) if old == new => false,
+            (old, new) => self
+                .config
+                .change_detector
+                .changed(&self.load(old), &self.load(new)),
+        }
+    }
+
+    /// Compares a recomputed result with the old one, returning the result to
+    /// store and whether it changed. With `GraphBuilder::keep_unchanged_results`
+    /// an unchanged result is replaced by the old one.
+    fn settle(&self, old: &Stored<R>, new: Stored<R>) -> (Stored<R>, bool) {
+        let changed = self.changed(old, &new);
+
+        if !changed && self.config.keep_unchanged_results {
+            (old.clone(), false)
+        } else {
+            (new, changed)
+        }
+    }
+
+    fn store(&self, result: R) -> Stored<R> {
+        match &self.config.compressor {
+            Some(compressor) => Stored::Compressed(compressor.compress(&result).into()),
+            None => Stored::Value(Arc::new(result)),
+        }
+    }
+
+    fn load(&self, stored: &Stored<R>) -> Arc<R> {
+        match stored {
+            Stored::Value(result) => result.clone(),
+            Stored::Compressed(bytes) => {
+                let compressor = self.config.compressor.as_ref();
+                let compressor = compressor.expect("compressed results require a compressor");
+                Arc::new(compressor.decompress(bytes))
+            }
+        }
+    }
+
+    /// Creates the next iteration with the new resolver, validating the old
+    /// results against it instead of resolving everything again. This is also
+    /// how the resolver is changed (e.g. to flip a feature flag inside it), but
+    /// since an old result is only resolved again when its dependencies
+    /// changed (or it was invalidated), state of the resolver that results
+    /// depend on should be read through an input query that's invalidated when
+    /// it changes.
+    pub fn increment(self: &Arc<Self>, resolver: impl ResolveQuery<Q, R> + 'static) -> Arc<Self> {
+        self.next_iteration(Arc::new(resolver))
+    }
+
+    /// Creates a new graph with the same resolver and configuration as this
+    /// iteration, but without any of its nodes, so everything is resolved from
+    /// scratch (e.g. to compare cold and warm performance). The new graph is
+    /// independent of this one: it has its own query log (if this one has
+    /// one), projections and subscribers.
+    pub fn clone_into_fresh(&self) -> Arc<Self> {
+        let graph = Self::create(
+            Arc::new(self.config.new_node_map()),
+            Default::default(),
+            self.resolver.clone(),
+            self.config.clone(),
+            Shared {
+                log: self
+                    .log
+                    .as_ref()
+                    .map(|_| Arc::new(Mutex::new(QueryLog::new()))),
+                ..Default::default()
+            },
+        );
+
+        graph.become_latest();
+        graph
+    }
+
+    /// Creates the next iteration and makes it the latest one.
+    fn next_iteration(self: &Arc<Self>, resolver: Arc<dyn ResolveQuery<Q, R>>) -> Arc<Self> {
+        let next = self.stage_iteration(resolver);
+        next.become_latest();
+        next
+    }
+
+    /// Creates the next iteration (without making it the latest one), carrying
+    /// over the invalidations that are still pending.
+    fn stage_iteration(&self, resolver: Arc<dyn ResolveQuery<Q, R>>) -> Arc<Self> {
+        let next = Self::create(
+            self.new.clone(),
+            self.namespaces.clone(),
+            resolver,
+            self.config.clone(),
+            self.shared(),
+        );
+
+        self.carry_invalidations(&next);
+
+        // The projections of queries that weren't resolved in this iteration
+        // can't be validated against anything in the next one.
+        self.retain_projections(|q| self.new.get(q).is_some_and(|slot| slot.get().is_some()));
+
+        next
+    }
+
+    /// Invalidates the queries invalidated in this iteration that weren't
+    /// resolved since (they weren't resolved at all, or were already resolved
+    /// when they were invalidated) in `next` too, so that invalidating right
+    /// before incrementing isn't lost.
+    fn carry_invalidations(&self, next: &Self) {
+        let pending = self
+            .invalidated
+            .read()
+            .iter()
+            .filter(|(q, stale)| **stale || self.new.get(q).is_none_or(|slot| slot.get().is_none()))
+            .map(|(q, _)| (q.clone(), false))
+            .collect::<Vec<_>>();
+
+        next.invalidated.write().extend(pending);
+    }
+
+    /// Increments the graph with the same resolver, but with the input query
+    /// set to `value` instead of resolving it, so that an input can be changed
+    /// without building a whole new resolver (and its state). Like any other
+    /// result, the input is only considered changed if `value` differs from
+    /// its old result.
+    ///
+    /// The query should be an input (see `ResolveQuery::is_input`), since
+    /// otherwise the resolver would resolve it again in the next iteration
+    /// (replacing the value).
+    pub fn set_input(self: &Arc<Self>, q: Q, value: R) -> Arc<Self> {
+        let next = self.next_iteration(self.resolver.clone());
+
+        // Nothing could've resolved the query yet, so it's always installed.
+        let _ = next.install(q, value, []);
+        next
+    }
+
+    /// Increments the graph like `increment`, but also eagerly resolves every
+    /// root query (a query without dependencies, i.e. an input) that was
+    /// resolved in this iteration with the new resolver, and returns the new
+    /// iteration along with every query that's potentially stale: the roots
+    /// whose result changed, the queries whose invalidation is carried over,
+    /// and everything that transitively depends on them.
+    ///
+    /// Staleness is normally only determined lazily when queries are made, so
+    /// this is useful for scheduling which queries to make first. The roots are
+    /// resolved in the new iteration, so that work isn't wasted.
+    pub fn increment_analyzed(
+        self: &Arc<Self>,
+        resolver: impl ResolveQuery<Q, R> + 'static,
+    ) -> (Arc<Self>, StdHashSet<Q>) {
+        let next = self.increment(resolver);
+        let stale = self.stale_queries(&next);
+
+        (next, stale)
+    }
+
+    /// Returns what incrementing this iteration with `resolver` would entail,
+    /// without incrementing it: the queries that would have to be resolved
+    /// again (like `increment_analyzed`), and the estimated number of calls to
+    /// the resolver it would take to bring them up to date.
+    ///
+    /// This resolves the roots with the new resolver in a scratch iteration
+    /// (like `query_with`), which is dropped when this returns, so nothing in
+    /// this graph changes. The estimate assumes every stale query actually has
+    /// to be resolved again, while some of them may end up being reused.
+    pub fn plan_increment(
+        self: &Arc<Self>,
+        resolver: impl ResolveQuery<Q, R> + 'static,
+    ) -> IncrementPlan<Q> {
+        let scratch = self.scratch_iteration(Arc::new(resolver));
+        let stale = self.stale_queries(&scratch);
+
+        let roots = scratch.stats();
+        let dependents = stale
+            .iter()
+            .filter(|q| {
+                self.new
+                    .get(q)
+                    .is_some_and(|node| node.get().is_some_and(|node| !node.edges_from.is_empty()))
+            })
+            .count();
+
+        IncrementPlan {
+            to_recompute: stale.into_iter().collect(),
+            estimated_cost: roots.fresh + roots.recomputed + dependents,
+        }
+    }
+
+    /// Resolves every root query that was resolved in this iteration in
+    /// `next`, and returns the potentially stale queries: the roots whose
+    /// result changed, the queries invalidated in `next`, and everything that
+    /// transitively depends on them.
+    fn stale_queries(&self, next: &Arc<Self>) -> StdHashSet<Q> {
+        let roots = self
+            .new
+            .iter()
+            .filter(|(_, node)| node.get().is_some_and(|node| node.edges_from.is_empty()))
+            .map(|(q, _)| q)
+            .collect::<Vec<_>>();
+
+        let mut changed = roots
+            .par_iter()
+            .filter(|q| {
+                let node = next.get_node(q);
+                next.get_or_resolve(&node, (*q).clone(), &Path::default())
+                    .changed
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+
+        changed.extend(next.invalidated.read().keys().cloned());
+
+        Self::transitive_dependents(&self.reverse_edges(), changed)
+    }
+
+    /// Creates a scratch iteration that validates against this one, for
+    /// resolving with another resolver without caching anything in this graph.
+    fn scratch_iteration(&self, resolver: Arc<dyn ResolveQuery<Q, R>>) -> Arc<Self> {
+        let scratch = Self::create(
+            self.new.clone(),
+            self.namespaces.clone(),
+            resolver,
+            self.config.clone(),
+            Shared {
+                log: None,
+                // The scratch iteration isn't part of this graph's lineage, so
+                // other graphs never validate against it.
+                lineage: Default::default(),
+                // What-if results aren't changes, so they aren't sent to the
+                // subscribers.
+                subscribers: Default::default(),
+                // Projections are validated against the results they were
+                // computed from, so sharing them is safe.
+                ..self.shared()
+            },
+        );
+
+        self.carry_invalidations(&scratch);
+        scratch
+    }
+
+    /// Gets the state shared with the iterations incremented from this one.
+    fn shared(&self) -> Shared<Q, R> {
+        Shared {
+            log: self.log.clone(),
+            lineage: self.lineage.clone(),
+            projections: self.projections.clone(),
+            subscribers: self.subscribers.clone(),
+            pinned: self.pinned.clone(),
+        }
+    }
+
+    /// Creates an iteration (with an empty new map) from its parts.
+    fn create(
+        old: QueryNodeMap<Q, R>,
+        old_namespaces: NamespaceIndex<Q>,
+        resolver: Arc<dyn ResolveQuery<Q, R>>,
+        config: Arc<Config<Q, R>>,
+        shared: Shared<Q, R>,
+    ) -> Arc<Self> {
+        let Shared {
+            log,
+            lineage,
+            projections,
+            subscribers,
+            pinned,
+        } = shared;
+
+        Arc::new(Self {
+            new: Arc::new(config.new_node_map()),
+            old,
+            base: None,
+            resolver,
+            config,
+            log,
+            resolved: AtomicUsize::new(0),
+            stats: StatsCounters::default(),
+            in_flight: AtomicUsize::new(0),
+            cancelled: AtomicBool::new(false),
+            clock: AtomicU64::new(0),
+            #[cfg(feature = "access_stats")]
+            created: Instant::now(),
+            evicting: Mutex::new(()),
+            impurity_reports: Mutex::new(Vec::new()),
+            lineage,
+            projections,
+            subscribers,
+            pinned,
+            invalidated: RwLock::new(HashMap::new()),
+            namespaces: Default::default(),
+            old_namespaces,
+            generation: AtomicU64::new(0),
+            reverse_edges: Mutex::new(None),
+            #[cfg(feature = "debug")]
+            resolution_order: Mutex::new(Vec::new()),
+        })
+    }
+}
+
+impl<Q: Clone + Eq + Hash + Send + Sync, R: Clone + Send + Sync> Graph<Q, R> {
+    pub fn query(self: &Arc<Self>, q: Q) -> R {
+        self.log_query(&q);
+
+        #[cfg(feature = "debug")]
+        if self.config.slow_clones.is_some() {
+            let result = self.query_internal(q.clone());
+            return self.clone_result(&q, &result);
+        }
+
+        self.query_internal(q).as_ref().clone()
+    }
+
+    /// Like `query`, but also returns whether the result changed in this
+    /// iteration (compared to the previous one, like `for_each_changed`, so a
+    /// query that's new in this iteration isn't considered changed).
+    pub fn force(self: &Arc<Self>, q: Q) -> (R, bool) {
+        self.log_query(&q);
+
+        let slot = self.get_node(&q);
+        let node = self.get_or_resolve(&slot, q.clone(), &Path::default());
+        let result = self.load(&node.result);
+
+        #[cfg(feature = "debug")]
+        if self.config.slow_clones.is_some() {
+            return (self.clone_result(&q, &result), node.changed);
+        }
+
+        (result.as_ref().clone(), node.changed)
+    }
+
+    /// Resolves every query (in parallel) and returns the results keyed by
+    /// query. Duplicate queries are only resolved (and returned) once.
+    pub fn par_query_all(
+        self: &Arc<Self>,
+        queries: impl IntoIterator<Item = Q>,
+    ) -> StdHashMap<Q, R> {
+        let queries = queries.into_iter().collect::<StdHashSet<_>>();
+
+        queries.iter().for_each(|q| self.log_query(q));
+
+        queries
+            .into_par_iter()
+            .map(|q| {
+                let result = self.query_internal(q.clone()).as_ref().clone();
+                (q, result)
+            })
+            .collect()
+    }
+
+    /// Resolves every query in parallel like `par_query_all`, but starts them
+    /// in order of priority (highest first), e.g. to warm the results of the
+    /// visible editor tabs before the background files. Duplicate queries are
+    /// resolved once, with their highest priority.
+    ///
+    /// This is best effort: the queries are handed to the rayon threads in
+    /// order, so a lower priority query never starts before a higher priority
+    /// one, but it may finish before it (and any query may end up waiting on a
+    /// lower priority one it depends on).
+    pub fn resolve_parallel_roots_with_priority<P: Ord>(
+        self: &Arc<Self>,
+        queries: impl IntoIterator<Item = (Q, P)>,
+    ) -> StdHashMap<Q, R> {
+        let mut prioritized = StdHashMap::<Q, P>::new();
+
+        for (q, priority) in queries {
+            match prioritized.get_mut(&q) {
+                Some(existing) if *existing >= priority => {}
+                Some(existing) => *existing = priority,
+                None => {
+                    prioritized.insert(q, priority);
+                }
+            }
+        }
+
+        let mut prioritized = prioritized.into_iter().collect::<Vec<_>>();
+        prioritized.sort_unstable_by(|(_, a), (_, b)| b.cmp(a));
+
+        let queries = prioritized.into_iter().map(|(q, _)| q).collect::<Vec<_>>();
+        let results = Mutex::new(StdHashMap::with_capacity(queries.len()));
+
+        self.query_in_order(&queries, |q, result| {
+            results.lock().insert(q.clone(), result);
+        });
+
+        results.into_inner()
+    }
+
+    /// Resolves every query in parallel like `par_query_all`, but sorts them
+    /// first and starts them in that order, returning the results in order
+    /// too. Queries that sort next to each other often share dependencies
+    /// (like the files of a directory), which improves locality, and the order
+    /// the queries are started in (and logged) is deterministic. Duplicate
+    /// queries are only resolved (and returned) once.
+    ///
+    /// Sorting can cost parallelism though: neighbouring queries that share
+    /// dependencies are started together, so their threads end up waiting on
+    /// each other for those dependencies instead of resolving something else.
+    pub fn query_all_ordered(
+        self: &Arc<Self>,
+        queries: impl IntoIterator<Item = Q>,
+    ) -> BTreeMap<Q, R>
+    where
+        Q: Ord,
+    {
+        let mut queries = queries.into_iter().collect::<Vec<_>>();
+        queries.sort_unstable();
+        queries.dedup();
+
+        let results = Mutex::new(BTreeMap::new());
+
+        self.query_in_order(&queries, |q, result| {
+            results.lock().insert(q.clone(), result);
+        });
+
+        results.into_inner()
+    }
+
+    /// Logs and resolves every query in parallel, handing them to the rayon
+    /// threads in order (so a query never starts before the ones before it),
+    /// and calls `f` with each result as it's resolved.
+    fn query_in_order(self: &Arc<Self>, queries: &[Q], f: impl Fn(&Q, R) + Sync) {
+        queries.iter().for_each(|q| self.log_query(q));
+
+        let next = AtomicUsize::new(0);
+
+        rayon::scope(|scope| {
+            for _ in 0..rayon::current_num_threads().min(queries.len()) {
+                scope.spawn(|_| {
+                    while let Some(q) = queries.get(next.fetch_add(1, Ordering::Relaxed)) {
+                        f(q, self.query_internal(q.clone()).as_ref().clone());
+                    }
+                });
+            }
+        });
+    }
+
+    /// Waits for the query to be resolved in this iteration by some other
+    /// thread and returns its result, without ever resolving it (or making
+    /// anything else resolve it). This is meant for observers that want the
+    /// eventual result without racing the threads doing the work.
+    ///
+    /// This blocks the calling thread (polling with a short backoff) until the
+    /// query is resolved, or returns `None` once the timeout elapses. If nothing
+    /// queries it in the meantime, it always times out.
+    pub fn await_resolution(&self, q: &Q, timeout: Duration) -> Option<R> {
+        let slot = self.get_node(q);
+        let node = Self::await_node(&slot, timeout)?;
+        Some(self.load(&node.result).as_ref().clone())
+    }
+
+    /// Compares the query's result in this graph with its result in `other`
+    /// (typically an earlier iteration of the same graph), returning both
+    /// results as `(other's, this graph's)`, i.e. before and after, if they
+    /// differ according to this graph's change detector. Returns `None` if
+    /// they're the same, or if the query isn't resolved in either graph
+    /// (nothing is resolved).
+    pub fn diff_results(&self, other: &Graph<Q, R>, q: &Q) -> Option<(R, R)> {
+        let before = other.resolved_result(q)?;
+        let after = self.resolved_result(q)?;
+
+        // Reused results are shared between iterations.
+        if Arc::ptr_eq(&before, &after) || !self.config.change_detector.changed(&before, &after) {
+            return None;
+        }
+
+        Some((before.as_ref().clone(), after.as_ref().clone()))
+    }
+
+    /// Resolves groups of queries, with the groups in parallel but the queries
+    /// of each group in order on a single worker (e.g. when they share some
+    /// expensive setup). The results have the same shape as `groups`.
+    pub fn query_batch_grouped(self: &Arc<Self>, groups: Vec<Vec<Q>>) -> Vec<Vec<R>> {
+        groups.iter().flatten().for_each(|q| self.log_query(q));
+
+        groups
+            .into_par_iter()
+            .map(|group| {
+                group
+                    .into_iter()
+                    .map(|q| self.query_internal(q).as_ref().clone())
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Resolves the query as if `resolver` were this graph's resolver, without
+    /// caching anything in this graph. This is useful for "what if" analysis,
+    /// like resolving the query with a single input patched.
+    ///
+    /// The override is resolved in a scratch iteration that validates against
+    /// the results in this graph (exactly like `increment` would), so only the
+    /// queries affected by the override are actually resolved again. The
+    /// scratch iteration is dropped when this returns.
+    pub fn query_with(self: &Arc<Self>, q: Q, resolver: impl ResolveQuery<Q, R> + 'static) -> R {
+        let scratch = self.scratch_iteration(Arc::new(resolver));
+        scratch.query_internal(q).as_ref().clone()
+    }
+
+    /// Resolves the query, but only recurses `depth` levels into its
+    /// dependencies (so a `depth` of 1 resolves the query and its immediate
+    /// dependencies). Deeper queries are replaced by the resolver's
+    /// `placeholder`, which is useful for rendering partial results quickly.
+    ///
+    /// Since a shallow result may contain placeholders, nothing resolved this
+    /// way is cached, and queries shared by several dependencies are resolved
+    /// once for each. Queries already resolved in this iteration are reused,
+    /// and queries without a placeholder are resolved (and cached) normally.
+    pub fn query_shallow(self: &Arc<Self>, q: Q, depth: usize) -> R {
+        self.resolve_shallow(q, depth).as_ref().clone()
+    }
+}
+
+/// Clears the resolving flag of a slot when its resolution ends (even if the
+/// resolver panics).
+struct Resolving<'a>(&'a AtomicBool);
+
+impl Drop for Resolving<'_> {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::Release);
+    }
+}
+
+/// Counts a resolution as in flight for as long as it's alive, so that the
+/// count is decremented even if the resolver panics.
+struct InFlight<'a>(&'a AtomicUsize);
+
+impl<'a> InFlight<'a> {
+    fn enter(in_flight: &'a AtomicUsize) -> Self {
+        in_flight.fetch_add(1, Ordering::Relaxed);
+        Self(in_flight)
+    }
+}
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// A `QueryRef` is a handle to a result stored in the graph (sharing its
+/// `Arc`), which derefs to the result. It isn't tied to the graph, so it keeps
+/// the result alive even after the graph (or the node) is dropped, and cloning
+/// it never clones the result.
+///
+/// Only `query_ref` (on the `Graph` and the `QueryResolver`) hands out
+/// `QueryRef`s, so it's the only way to query results that don't implement
+/// `Clone`. `query` and the rest of the API that returns results by value
+/// still require `R: Clone`.
+pub struct QueryRef<R> {
+    result: Arc<R>,
+}
+
+impl<R> QueryRef<R> {
+    fn new(result: Arc<R>) -> Self {
+        Self { result }
+    }
+}
+
+impl<R> Clone for QueryRef<R> {
+    fn clone(&self) -> Self {
+        Self::new(self.result.clone())
+    }
+}
+
+impl<R> Deref for QueryRef<R> {
+    type Target = R;
+
+    fn deref(&self) -> &Self::Target {
+        &self.result
+    }
+}
+
+impl<R: Debug> Debug for QueryRef<R> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.result.as_ref().fmt(f)
+    }
+}
+
+pub struct QueryResolver<Q, R> {
+    graph: Arc<Graph<Q, R>>,
+    /// The queries this resolution depends on. It's behind a lock since a
+    /// resolver is free to make its queries in parallel.
+    edges_from: Mutex<HashSet<Q>>,
+    /// The queries on other graphs this resolution depends on.
+    external_edges: Mutex<Vec<Arc<dyn external::ExternalEdge>>>,
+    /// The queries this resolution only depends on parts of (see
+    /// `query_part`).
+    parts: Mutex<part::PartEdges<Q, R>>,
+    /// How many more levels of dependencies to resolve (see
+    /// `Graph::query_shallow`), or `None` to resolve all of them.
+    depth: Option<usize>,
+    /// The old result of the query being resolved (if it had one), which is
+    /// only loaded (decompressed) if the resolver asks for it.
+    old: Option<Stored<R>>,
+    old_result: OnceLock<Arc<R>>,
+    /// The path of queries being resolved that led to this resolution (ending
+    /// with the query being resolved), used to detect cycles.
+    path: Path<Q>,
+}
+
+impl<Q: Clone + Eq + Hash + Send + Sync, R: Send + Sync> QueryResolver<Q, R> {
+    fn new(graph: Arc<Graph<Q, R>>, path: Path<Q>) -> Self {
+        let edges_from = graph.new_edge_set(0);
+        Self::with_edges(graph, path, edges_from)
+    }
+
+    fn with_edges(graph: Arc<Graph<Q, R>>, path: Path<Q>, edges_from: HashSet<Q>) -> Self {
+        Self {
             graph,
-            edges_from: RefCell::new(HashSet::new()),
+            edges_from: Mutex::new(edges_from),
+            external_edges: Mutex::new(Vec::new()),
+            parts: Mutex::new(HashMap::new()),
+            depth: None,
+            old: None,
+            old_result: OnceLock::new(),
+            path,
         }
     }
 
-    pub fn query(&self, q: Q) -> R {
-        let result = self.graph.query(q.clone());
-        self.edges_from.borrow_mut().insert(q);
+    /// Creates the resolver for recomputing a query, which most likely depends
+    /// on about as many queries as it did before, so its edges are pre-sized
+    /// with the old node's edge count.
+    fn with_old(graph: Arc<Graph<Q, R>>, path: Path<Q>, old: &Node<Q, R>) -> Self {
+        let edges_from = graph.new_edge_set(old.edges_from.len());
+
+        Self {
+            old: Some(old.result.clone()),
+            ..Self::with_edges(graph, path, edges_from)
+        }
+    }
+
+    fn shallow(graph: Arc<Graph<Q, R>>, depth: usize) -> Self {
+        Self {
+            depth: Some(depth),
+            ..Self::new(graph, Path::default())
+        }
+    }
+
+    /// Returns the result the query being resolved had in the previous
+    /// iteration (if it had one). A resolver that can cheaply prove that
+    /// nothing relevant to it changed can return (a clone of) the old result
+    /// instead of computing it again.
+    ///
+    /// The returned result still goes through change detection like any other
+    /// result (it's just detected as unchanged), so returning it is always
+    /// correct as long as the proof is. Dependencies are only recorded for the
+    /// queries made during this resolution though, so the proof should still
+    /// query whatever the result depends on, or those dependencies are lost.
+    pub fn old_result(&self) -> Option<&R> {
+        let old = self.old.as_ref()?;
+        Some(self.old_result.get_or_init(|| self.graph.load(old)))
+    }
+
+    /// Reserves capacity for at least `additional` more dependencies, which
+    /// saves growing the edge set repeatedly when a resolver is about to make a
+    /// lot of queries (e.g. one for every file).
+    pub fn reserve_edges(&self, additional: usize) {
+        self.edges_from.lock().reserve(additional);
+    }
+
+    /// Takes the dependencies of the resolution, along with the ones it only
+    /// depends on parts of. A dependency that was also queried in full isn't
+    /// one of the parts.
+    fn take_edges(&self) -> (Arc<HashSet<Q>>, Parts<Q, R>) {
+        let mut edges_from = std::mem::take(&mut *self.edges_from.lock());
+        let mut parts = std::mem::take(&mut *self.parts.lock());
+
+        parts.retain(|q, _| !edges_from.contains(q));
+        edges_from.extend(parts.keys().cloned());
+
+        (Arc::new(edges_from), Arc::new(parts))
+    }
+
+    fn take_external_edges(&self) -> ExternalEdges {
+        std::mem::take(&mut *self.external_edges.lock()).into()
+    }
+
+    /// Like `query`, but returns a handle to the result instead of cloning it
+    /// (see [`QueryRef`]), so it doesn't require `R: Clone`.
+    pub fn query_ref(&self, q: Q) -> QueryRef<R> {
+        QueryRef::new(self.query_internal(q))
+    }
+
+    fn query_internal(&self, q: Q) -> Arc<R> {
+        let (result, recovered) = self.query_untracked(&q);
+
+        // The recovered result isn't recorded as a dependency, since that
+        // would make the dependency graph itself cyclic.
+        if !recovered {
+            self.edges_from.lock().insert(q);
+        }
         // TODO: edges_to (maybe?).
         result
     }
+
+    /// Queries the query without recording it as a dependency, returning
+    /// whether the result was recovered from a cycle (see
+    /// `ResolveQuery::recover_cycle`).
+    fn query_untracked(&self, q: &Q) -> (Arc<R>, bool) {
+        let result = match self.depth {
+            None => return self.graph.query_or_recover(q.clone(), &self.path),
+            Some(0) => match self.graph.resolved_result(q) {
+                Some(result) => result,
+                None => match self.graph.resolver.placeholder(q) {
+                    Some(placeholder) => Arc::new(placeholder),
+                    None => self.graph.query_internal(q.clone()),
+                },
+            },
+            Some(depth) => self.graph.resolve_shallow(q.clone(), depth - 1),
+        };
+        (result, false)
+    }
+}
+
+impl<Q: Clone + Eq + Hash + Send + Sync, R: Clone + Send + Sync> QueryResolver<Q, R> {
+    pub fn query(&self, q: Q) -> R {
+        #[cfg(feature = "debug")]
+        if self.graph.config.slow_clones.is_some() {
+            let result = self.query_internal(q.clone());
+            return self.graph.clone_result(&q, &result);
+        }
+
+        self.query_internal(q).as_ref().clone()
+    }
+
+    /// Returns the result of the query if it was already resolved in this
+    /// iteration (e.g. by a sibling resolution), without ever resolving it, so
+    /// a resolver can make opportunistic decisions based on what's already
+    /// available. It only sees the results in this iteration's map, not the
+    /// old results of the previous iteration.
+    ///
+    /// A result that's returned is recorded as a dependency, like with
+    /// `query`. A missing one isn't (there's nothing to depend on), so whether
+    /// it's there depends on timing, and the result of the resolution must not
+    /// depend on it (e.g. use it as a shortcut to a result that's computed the
+    /// same way either way).
+    pub fn try_peek(&self, q: &Q) -> Option<R> {
+        let result = self.graph.resolved_result(q)?;
+        self.edges_from.lock().insert(q.clone());
+        Some(result.as_ref().clone())
+    }
 }
 
 pub trait ResolveQuery<Q, R>: Send + Sync {
     fn resolve(&self, q: Q, resolve: Arc<QueryResolver<Q, R>>) -> R;
+
+    /// The namespace of the query (e.g. the module or crate it belongs to),
+    /// which lets a whole group of queries be invalidated at once with
+    /// `Graph::invalidate_namespace`. Queries without a namespace (the
+    /// default) can only be invalidated individually.
+    fn namespace(&self, _q: &Q) -> Option<String> {
+        None
+    }
+
+    /// Whether the query is an input: a query with no dependencies whose result
+    /// only changes when it's explicitly invalidated (with `Graph::invalidate`).
+    /// The old results of inputs are reused without resolving them again, which
+    /// saves resolving every root query in every iteration.
+    fn is_input(&self, _q: &Q) -> bool {
+        false
+    }
+
+    /// Whether the query is volatile: a query that reads state outside of the
+    /// graph (like the clock or the environment), so its old result is never
+    /// reused. Queries that depend on a volatile query are still memoized, so
+    /// they're reported by `Graph::purity_violations`.
+    fn is_volatile(&self, _q: &Q) -> bool {
+        false
+    }
+
+    /// The result to break a cycle with when `q` is queried while it's being
+    /// resolved further up the path (e.g. by mutually recursive definitions).
+    /// `cycle` starts and ends with `q`, and each query in it is queried by
+    /// the one before it. Returning `Some` hands the result to the query that
+    /// closed the cycle (without caching it as the result of `q`), while
+    /// `None` (the default) fails with a cycle error as usual.
+    ///
+    /// The recovered result isn't recorded as a dependency, so the query that
+    /// closed the cycle is only resolved again when its other dependencies
+    /// change.
+    fn recover_cycle(&self, _q: &Q, _cycle: &[Q]) -> Option<R> {
+        None
+    }
+
+    /// The "not yet available" result used in place of queries beyond the
+    /// depth of `Graph::query_shallow`. When this returns `None` (the default)
+    /// the query is resolved normally instead.
+    fn placeholder(&self, _q: &Q) -> Option<R> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A dense diamond: `Top` depends on every `Mid`, every `Mid` depends on
+    /// every `Leaf`, and every `Leaf` depends on `Input`.
+    #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+    enum Diamond {
+        Top,
+        Mid(u32),
+        Leaf(u32),
+        Input,
+    }
+
+    struct DiamondResolver {
+        input: u32,
+    }
+
+    impl ResolveQuery<Diamond, u32> for DiamondResolver {
+        fn resolve(&self, q: Diamond, resolve: Arc<QueryResolver<Diamond, u32>>) -> u32 {
+            match q {
+                Diamond::Top => (0..8).map(|i| resolve.query(Diamond::Mid(i))).sum(),
+                Diamond::Mid(_) => (0..8).map(|i| resolve.query(Diamond::Leaf(i))).sum(),
+                Diamond::Leaf(i) => resolve.query(Diamond::Input) + i,
+                Diamond::Input => self.input,
+            }
+        }
+    }
+
+    /// `Top` depends on `Flaky`, which panics the first time it's resolved.
+    struct FlakyResolver {
+        panicked: AtomicBool,
+    }
+
+    impl ResolveQuery<&'static str, u32> for FlakyResolver {
+        fn resolve(&self, q: &'static str, resolve: Arc<QueryResolver<&'static str, u32>>) -> u32 {
+            match q {
+                "Top" => resolve.query("Flaky") + 1,
+                _ => {
+                    if !self.panicked.swap(true, Ordering::Relaxed) {
+                        panic!("flaky resolver");
+                    }
+
+                    1
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn panicking_resolver_can_be_retried() {
+        let graph = Graph::new(FlakyResolver {
+            panicked: AtomicBool::new(false),
+        });
+
+        let panicked = panic::catch_unwind(AssertUnwindSafe(|| graph.query("Top")));
+        assert!(panicked.is_err());
+
+        // Neither the query that panicked nor the one depending on it were
+        // resolved (or left resolving), so both are simply resolved again.
+        let untouched = |q| {
+            graph
+                .new
+                .get(&q)
+                .is_none_or(|slot| slot.get().is_none() && !slot.resolving.load(Ordering::Acquire))
+        };
+
+        assert!(untouched("Flaky") && untouched("Top"));
+        assert_eq!(graph.query("Top"), 2);
+        assert_eq!(graph.query("Flaky"), 1);
+    }
+
+    /// `Sum(n)` adds up the inputs below `n`. Inputs are read from the shared
+    /// values, so they only change when they're invalidated.
+    #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+    enum Inputs {
+        Sum(u32),
+        Input(u32),
+    }
+
+    #[derive(Clone, Default)]
+    struct InputsResolver {
+        values: Arc<Mutex<StdHashMap<u32, u32>>>,
+        resolved: Arc<Mutex<Vec<Inputs>>>,
+    }
+
+    impl ResolveQuery<Inputs, u32> for InputsResolver {
+        fn resolve(&self, q: Inputs, resolve: Arc<QueryResolver<Inputs, u32>>) -> u32 {
+            self.resolved.lock().push(q.clone());
+
+            match q {
+                Inputs::Sum(n) => (0..n).map(|i| resolve.query(Inputs::Input(i))).sum(),
+                Inputs::Input(i) => self.values.lock().get(&i).copied().unwrap_or(0),
+            }
+        }
+
+        fn is_input(&self, q: &Inputs) -> bool {
+            matches!(q, Inputs::Input(_))
+        }
+    }
+
+    #[test]
+    fn invalidations_are_carried_over_increments() {
+        let resolver = InputsResolver::default();
+        let graph = Graph::new(resolver.clone());
+
+        assert_eq!(graph.query(Inputs::Sum(4)), 0);
+
+        // The input is invalidated before incrementing, after it was already
+        // resolved in this iteration.
+        resolver.values.lock().insert(1, 10);
+        graph.invalidate(Inputs::Input(1));
+        resolver.resolved.lock().clear();
+
+        let graph = graph.increment(resolver.clone());
+
+        assert_eq!(graph.query(Inputs::Sum(4)), 10);
+
+        // Only the invalidated input (and the sums depending on it) were
+        // resolved again, the other inputs were reused.
+        let resolved = resolver.resolved.lock();
+
+        assert!(resolved.contains(&Inputs::Input(1)));
+        assert!(resolved.contains(&Inputs::Sum(4)));
+        assert!(!resolved.contains(&Inputs::Input(0)));
+        assert!(!resolved.contains(&Inputs::Input(2)));
+    }
+
+    /// Resolves every query to a freshly allocated (but equal) tree.
+    struct TreeResolver;
+
+    impl ResolveQuery<u32, Arc<String>> for TreeResolver {
+        fn resolve(&self, q: u32, _resolve: Arc<QueryResolver<u32, Arc<String>>>) -> Arc<String> {
+            Arc::new(format!("tree {}", q))
+        }
+    }
+
+    #[test]
+    fn unchanged_results_keep_their_pointers() {
+        for keep in [false, true] {
+            let builder = GraphBuilder::new();
+            let builder = if keep {
+                builder.keep_unchanged_results()
+            } else {
+                builder
+            };
+
+            let graph = builder.build(TreeResolver);
+            let old = graph.query(1);
+
+            let graph = graph.increment(TreeResolver);
+            let new = graph.query(1);
+
+            assert_eq!(old, new);
+            assert_eq!(Arc::ptr_eq(&old, &new), keep);
+            assert_eq!(graph.stats().recomputed, 1);
+        }
+    }
+
+    #[test]
+    fn reverse_edges_survive_a_wrapped_generation() {
+        let graph = Graph::new(InputsResolver::default());
+
+        // Cache the reverse edges at the last generation before wrapping.
+        graph.query(Inputs::Sum(1));
+        graph.generation.store(u64::MAX, Ordering::Release);
+        let dependents = graph.queries_depending_on(&Inputs::Input(0));
+        assert_eq!(dependents.len(), 2);
+
+        // Resolving another query (and a new input) wraps the generation, which
+        // still invalidates the cached reverse edges.
+        graph.query(Inputs::Sum(2));
+        assert_eq!(graph.generation.load(Ordering::Acquire), 1);
+        assert_eq!(graph.queries_depending_on(&Inputs::Input(0)).len(), 3);
+    }
+
+    #[test]
+    fn installing_keeps_the_resolution_count() {
+        let resolver = InputsResolver::default();
+        let graph = Graph::new(resolver.clone());
+
+        // The sum is resolved twice, the second time because its input changed.
+        assert_eq!(graph.query(Inputs::Sum(1)), 0);
+        resolver.values.lock().insert(0, 1);
+        graph.invalidate(Inputs::Input(0));
+        let graph = graph.increment(resolver.clone());
+        assert_eq!(graph.query(Inputs::Sum(1)), 1);
+
+        let graph = graph.increment(resolver);
+        graph
+            .install(Inputs::Sum(1), 7, [Inputs::Input(0)])
+            .unwrap();
+
+        assert_eq!(graph.hot_queries(1), [(Inputs::Sum(1), 2)]);
+    }
+
+    /// A result that can't be cloned.
+    #[derive(Debug, PartialEq)]
+    struct Unique(String);
+
+    /// `n` is the name of `n - 1` with another `'a'`.
+    struct UniqueResolver;
+
+    impl ResolveQuery<u32, Unique> for UniqueResolver {
+        fn resolve(&self, q: u32, resolve: Arc<QueryResolver<u32, Unique>>) -> Unique {
+            match q {
+                0 => Unique(String::new()),
+                q => Unique(format!("{}a", resolve.query_ref(q - 1).0)),
+            }
+        }
+    }
+
+    #[test]
+    fn query_refs_dont_need_clone() {
+        let graph = Graph::new(UniqueResolver);
+        let result = graph.query_ref(3);
+        let shared = result.clone();
+        drop(graph);
+
+        // The handles share the result, which outlives the graph.
+        assert_eq!(*result, Unique("aaa".to_string()));
+        assert!(std::ptr::eq(&*result, &*shared));
+    }
+
+    /// The whole program, whose size is read from outside the graph.
+    struct ProgramResolver(Arc<AtomicUsize>);
+
+    impl ResolveQuery<(), usize> for ProgramResolver {
+        fn resolve(&self, _q: (), _resolve: Arc<QueryResolver<(), usize>>) -> usize {
+            self.0.load(Ordering::Relaxed)
+        }
+
+        fn is_input(&self, _q: &()) -> bool {
+            true
+        }
+    }
+
+    #[test]
+    fn singleton_graphs() {
+        let size = Arc::new(AtomicUsize::new(1));
+        let graph = Graph::singleton(ProgramResolver(size.clone()));
+
+        assert!(matches!(&*graph.new, NodeMap::Singleton(_)));
+        assert_eq!(graph.query(()), 1);
+
+        // The input is reused until it's invalidated.
+        size.store(2, Ordering::Relaxed);
+        let graph = graph.increment(ProgramResolver(size.clone()));
+        assert_eq!(graph.query(()), 1);
+
+        graph.invalidate(());
+        let graph = graph.increment(ProgramResolver(size.clone()));
+        assert_eq!(graph.query(()), 2);
+
+        let mut changed = Vec::new();
+        graph.for_each_changed(|q, result| changed.push((*q, *result)));
+        assert_eq!(changed, [((), 2)]);
+
+        // The node is never dropped.
+        graph.retain(|_| false);
+        assert_eq!(graph.new.len(), 1);
+        assert_eq!(graph.query(()), 2);
+    }
+
+    #[test]
+    fn diamond_nodes_are_validated_once() {
+        let validations = Arc::new(Mutex::new(StdHashMap::<Diamond, usize>::new()));
+
+        let graph = GraphBuilder::new()
+            .on_resolve({
+                let validations = validations.clone();
+                move |q: &Diamond, _| *validations.lock().entry(q.clone()).or_default() += 1
+            })
+            .build(DiamondResolver { input: 1 });
+
+        graph.query(Diamond::Top);
+
+        // Once with an unchanged input (so every node is reused), and once with
+        // a changed one (so every node is recomputed).
+        // Several threads, so that dependents race to validate the same nodes.
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(4)
+            .build()
+            .unwrap();
+
+        for input in [1, 2] {
+            validations.lock().clear();
+
+            let graph = graph.increment(DiamondResolver { input });
+            pool.install(|| graph.query(Diamond::Top));
+
+            let validations = validations.lock();
+
+            assert_eq!(validations.len(), 8 + 8 + 2);
+            assert!(validations.values().all(|count| *count == 1));
+        }
+    }
 }
@@ -0,0 +1,404 @@
+use std::{
+    cell::{Cell, OnceCell, RefCell},
+    hash::Hash,
+    rc::Rc,
+};
+
+use hashbrown::{HashMap, HashSet};
+
+/// Like [`ResolveQuery`](crate::ResolveQuery), but for resolvers that aren't
+/// `Send` or `Sync` (e.g. ones wrapping a thread-affine FFI handle), which are
+/// resolved by a [`LocalGraph`].
+pub trait LocalResolveQuery<Q, R> {
+    fn resolve(&self, q: Q, resolve: &LocalQueryResolver<'_, Q, R>) -> R;
+
+    /// See `ResolveQuery::is_input`.
+    fn is_input(&self, _q: &Q) -> bool {
+        false
+    }
+
+    /// See `ResolveQuery::is_volatile`.
+    fn is_volatile(&self, _q: &Q) -> bool {
+        false
+    }
+
+    /// See `ResolveQuery::recover_cycle`.
+    fn recover_cycle(&self, _q: &Q, _cycle: &[Q]) -> Option<R> {
+        None
+    }
+}
+
+/// A `LocalGraph` is a single-threaded graph, for resolvers that aren't `Send`
+/// or `Sync` (see [`LocalResolveQuery`]). Every query is resolved (and
+/// validated) on the thread that queries it, so neither the queries, the
+/// results nor the resolver have to be `Send` or `Sync`, and neither is the
+/// graph.
+///
+/// It has the core of a `Graph`: queries are resolved once per iteration, and
+/// incrementing validates the old results (with early cutoff) instead of
+/// resolving everything again. The rest of the `Graph` API (parallel queries,
+/// snapshots, eviction, statistics, ...) isn't available.
+pub struct LocalGraph<Q, R> {
+    new: LocalNodeMap<Q, R>,
+    old: LocalNodeMap<Q, R>,
+    resolver: Box<dyn LocalResolveQuery<Q, R>>,
+    /// The invalidated queries, and whether each one was already resolved when
+    /// it was invalidated (see `Graph::invalidate`).
+    invalidated: RefCell<HashMap<Q, bool>>,
+    /// The queries being resolved, innermost last, to find cycles.
+    path: RefCell<Vec<Q>>,
+}
+
+type LocalNodeMap<Q, R> = Rc<RefCell<HashMap<Q, Rc<LocalSlot<Q, R>>>>>;
+
+struct LocalSlot<Q, R> {
+    node: OnceCell<LocalNode<Q, R>>,
+    resolving: Cell<bool>,
+}
+
+struct LocalNode<Q, R> {
+    result: Rc<R>,
+    /// Whether the result changed since the old node. A node without an old
+    /// node to compare against is considered changed.
+    changed: bool,
+    edges_from: Rc<HashSet<Q>>,
+}
+
+impl<Q, R> LocalSlot<Q, R> {
+    fn node(&self) -> &LocalNode<Q, R> {
+        self.node.get().expect("the slot was resolved")
+    }
+}
+
+/// Marks a slot as resolving (and its query as on the path) until it's
+/// dropped, even if the resolution panics.
+struct Resolving<'a, Q, R> {
+    graph: &'a LocalGraph<Q, R>,
+    slot: &'a LocalSlot<Q, R>,
+}
+
+impl<'a, Q, R> Resolving<'a, Q, R> {
+    fn enter(graph: &'a LocalGraph<Q, R>, slot: &'a LocalSlot<Q, R>, q: Q) -> Self {
+        slot.resolving.set(true);
+        graph.path.borrow_mut().push(q);
+        Self { graph, slot }
+    }
+}
+
+impl<Q, R> Drop for Resolving<'_, Q, R> {
+    fn drop(&mut self) {
+        self.graph.path.borrow_mut().pop();
+        self.slot.resolving.set(false);
+    }
+}
+
+impl<Q: Clone + Eq + Hash, R: PartialEq> LocalGraph<Q, R> {
+    pub fn new(resolver: impl LocalResolveQuery<Q, R> + 'static) -> Self {
+        Self {
+            new: Default::default(),
+            old: Default::default(),
+            resolver: Box::new(resolver),
+            invalidated: Default::default(),
+            path: Default::default(),
+        }
+    }
+
+    /// Creates the next iteration with the new resolver (see
+    /// `Graph::increment`), carrying over the invalidations that are still
+    /// pending. This iteration can still be queried afterwards.
+    pub fn increment(&self, resolver: impl LocalResolveQuery<Q, R> + 'static) -> Self {
+        let pending = self
+            .invalidated
+            .borrow()
+            .iter()
+            .filter(|(q, stale)| **stale || !self.is_resolved(q))
+            .map(|(q, _)| (q.clone(), false))
+            .collect();
+
+        Self {
+            new: Default::default(),
+            old: self.new.clone(),
+            resolver: Box::new(resolver),
+            invalidated: RefCell::new(pending),
+            path: Default::default(),
+        }
+    }
+
+    /// See `Graph::invalidate`.
+    pub fn invalidate(&self, q: Q) {
+        let resolved = self.is_resolved(&q);
+        *self.invalidated.borrow_mut().entry(q).or_default() |= resolved;
+    }
+
+    fn is_resolved(&self, q: &Q) -> bool {
+        let new = self.new.borrow();
+        new.get(q).is_some_and(|slot| slot.node.get().is_some())
+    }
+
+    /// Gets the slot of the query, resolving it first if it isn't resolved.
+    /// With `recover` a cycle the resolver recovers from gives back the
+    /// recovered result instead of panicking.
+    fn get_or_resolve(&self, q: &Q, recover: bool) -> Result<Rc<LocalSlot<Q, R>>, R> {
+        let slot = self.new.borrow().get(q).cloned();
+        let slot = slot.unwrap_or_else(|| {
+            let slot = Rc::new(LocalSlot {
+                node: OnceCell::new(),
+                resolving: Cell::new(false),
+            });
+
+            self.new.borrow_mut().insert(q.clone(), slot.clone());
+            slot
+        });
+
+        if slot.node.get().is_some() {
+            return Ok(slot);
+        }
+
+        if slot.resolving.get() {
+            let path = self.path.borrow();
+            let start = path.iter().rposition(|query| query == q).unwrap_or(0);
+            let cycle = path[start..].iter().chain([q]).cloned().collect::<Vec<_>>();
+            drop(path);
+
+            if recover {
+                if let Some(result) = self.resolver.recover_cycle(q, &cycle) {
+                    return Err(result);
+                }
+            }
+
+            panic!("query cycle detected");
+        }
+
+        let resolving = Resolving::enter(self, &slot, q.clone());
+        let node = self.resolve_node(q.clone());
+        drop(resolving);
+
+        if slot.node.set(node).is_err() {
+            unreachable!("a slot is only resolved once");
+        }
+
+        Ok(slot)
+    }
+
+    fn resolve_node(&self, q: Q) -> LocalNode<Q, R> {
+        let old = self.old.borrow().get(&q).cloned();
+        let Some(old_node) = old.as_ref().and_then(|old| old.node.get()) else {
+            return self.resolve_against(q, None);
+        };
+
+        let stale = self.invalidated.borrow().contains_key(&q) || self.resolver.is_volatile(&q);
+
+        let reusable = if old_node.edges_from.is_empty() {
+            // A root can only be reused if it's an input, since nothing else
+            // tells whether it changed.
+            !stale && self.resolver.is_input(&q)
+        } else {
+            !stale
+                && !old_node.edges_from.iter().any(|parent| {
+                    match self.get_or_resolve(parent, false) {
+                        Ok(slot) => slot.node().changed,
+                        Err(_) => unreachable!("cycles are only recovered from when asked to"),
+                    }
+                })
+        };
+
+        if reusable {
+            LocalNode {
+                result: old_node.result.clone(),
+                changed: false,
+                edges_from: old_node.edges_from.clone(),
+            }
+        } else {
+            self.resolve_against(q, Some(old_node))
+        }
+    }
+
+    /// Resolves the query with the resolver, comparing the result against the
+    /// old node (if there is one).
+    fn resolve_against(&self, q: Q, old_node: Option<&LocalNode<Q, R>>) -> LocalNode<Q, R> {
+        let resolver = LocalQueryResolver {
+            graph: self,
+            edges_from: RefCell::new(HashSet::new()),
+        };
+
+        let result = self.resolver.resolve(q, &resolver);
+
+        LocalNode {
+            changed: old_node.is_none_or(|old_node| *old_node.result != result),
+            result: Rc::new(result),
+            edges_from: Rc::new(resolver.edges_from.into_inner()),
+        }
+    }
+}
+
+impl<Q: Clone + Eq + Hash, R: PartialEq + Clone> LocalGraph<Q, R> {
+    pub fn query(&self, q: Q) -> R {
+        match self.get_or_resolve(&q, false) {
+            Ok(slot) => slot.node().result.as_ref().clone(),
+            Err(_) => unreachable!("cycles are only recovered from when asked to"),
+        }
+    }
+}
+
+/// What a [`LocalResolveQuery`] queries its dependencies with (see
+/// `QueryResolver`).
+pub struct LocalQueryResolver<'g, Q, R> {
+    graph: &'g LocalGraph<Q, R>,
+    edges_from: RefCell<HashSet<Q>>,
+}
+
+impl<Q: Clone + Eq + Hash, R: PartialEq + Clone> LocalQueryResolver<'_, Q, R> {
+    /// Queries a dependency of the query being resolved (see
+    /// `QueryResolver::query`).
+    pub fn query(&self, q: Q) -> R {
+        match self.graph.get_or_resolve(&q, true) {
+            Ok(slot) => {
+                self.edges_from.borrow_mut().insert(q);
+                slot.node().result.as_ref().clone()
+            }
+            // The recovered result isn't recorded as a dependency, since that
+            // would make the dependency graph itself cyclic.
+            Err(recovered) => recovered,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+    enum Query {
+        Length(&'static str),
+        Total,
+        Input(&'static str),
+    }
+
+    /// Reads the inputs from state that isn't `Send` (like an FFI handle would
+    /// be), and records what it resolves.
+    #[derive(Clone, Default)]
+    struct Resolver {
+        inputs: Rc<RefCell<HashMap<&'static str, Rc<str>>>>,
+        resolved: Rc<RefCell<Vec<Query>>>,
+    }
+
+    impl LocalResolveQuery<Query, Rc<str>> for Resolver {
+        fn resolve(&self, q: Query, resolve: &LocalQueryResolver<'_, Query, Rc<str>>) -> Rc<str> {
+            self.resolved.borrow_mut().push(q.clone());
+
+            match q {
+                Query::Input(name) => self.inputs.borrow()[name].clone(),
+                Query::Length(name) => resolve.query(Query::Input(name)).len().to_string().into(),
+                Query::Total => {
+                    let a = resolve.query(Query::Length("a")).parse::<usize>().unwrap();
+                    let b = resolve.query(Query::Length("b")).parse::<usize>().unwrap();
+                    (a + b).to_string().into()
+                }
+            }
+        }
+
+        fn is_input(&self, q: &Query) -> bool {
+            matches!(q, Query::Input(_))
+        }
+    }
+
+    #[test]
+    fn unchanged_results_cut_off_dependents() {
+        let resolver = Resolver::default();
+        resolver.inputs.borrow_mut().insert("a", "one".into());
+        resolver.inputs.borrow_mut().insert("b", "three".into());
+
+        let graph = LocalGraph::new(resolver.clone());
+        assert_eq!(&*graph.query(Query::Total), "8");
+
+        // The new input has the same length, so the total is reused.
+        resolver.inputs.borrow_mut().insert("a", "two".into());
+        graph.invalidate(Query::Input("a"));
+        resolver.resolved.borrow_mut().clear();
+
+        let graph = graph.increment(resolver.clone());
+
+        assert_eq!(&*graph.query(Query::Total), "8");
+        assert_eq!(
+            *resolver.resolved.borrow(),
+            [Query::Input("a"), Query::Length("a")]
+        );
+
+        // A longer input changes the total.
+        resolver
+            .inputs
+            .borrow_mut()
+            .insert("b", "seven".repeat(2).into());
+        graph.invalidate(Query::Input("b"));
+        resolver.resolved.borrow_mut().clear();
+
+        let graph = graph.increment(resolver.clone());
+
+        assert_eq!(&*graph.query(Query::Total), "13");
+        assert_eq!(
+            *resolver.resolved.borrow(),
+            [Query::Input("b"), Query::Length("b"), Query::Total]
+        );
+    }
+
+    /// `Even(n)` and `Odd(n)` are mutually recursive, and the cycle is
+    /// recovered from with `false`.
+    struct Parity;
+
+    impl LocalResolveQuery<(bool, u32), bool> for Parity {
+        fn resolve(
+            &self,
+            (even, n): (bool, u32),
+            resolve: &LocalQueryResolver<'_, (bool, u32), bool>,
+        ) -> bool {
+            match n {
+                0 => even,
+                n => resolve.query((!even, n - 1)),
+            }
+        }
+
+        fn recover_cycle(&self, _q: &(bool, u32), _cycle: &[(bool, u32)]) -> Option<bool> {
+            Some(false)
+        }
+    }
+
+    #[test]
+    fn recursion_without_cycles() {
+        let graph = LocalGraph::new(Parity);
+
+        assert!(graph.query((true, 10)));
+        assert!(!graph.query((false, 10)));
+        assert!(graph.query((false, 7)));
+    }
+
+    /// Queries itself, which is only recovered from when `recover` is set.
+    struct Itself {
+        recover: bool,
+    }
+
+    impl LocalResolveQuery<u32, u32> for Itself {
+        fn resolve(&self, q: u32, resolve: &LocalQueryResolver<'_, u32, u32>) -> u32 {
+            resolve.query(q) + 1
+        }
+
+        fn recover_cycle(&self, q: &u32, cycle: &[u32]) -> Option<u32> {
+            assert_eq!(cycle, [*q, *q]);
+            self.recover.then_some(0)
+        }
+    }
+
+    #[test]
+    fn cycles() {
+        assert_eq!(LocalGraph::new(Itself { recover: true }).query(7), 1);
+
+        let graph = LocalGraph::new(Itself { recover: false });
+        let panic = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| graph.query(7)));
+
+        assert!(panic.is_err());
+
+        // The failed resolution doesn't leave the query resolving.
+        let panic = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| graph.query(7)));
+        assert!(panic.is_err());
+        assert!(graph.path.borrow().is_empty());
+    }
+}
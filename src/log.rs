@@ -0,0 +1,77 @@
+use std::{hash::Hash, sync::Arc};
+
+use crate::Graph;
+
+/// A `QueryLog` is the ordered sequence of top-level queries made against a
+/// graph (and every iteration incremented from it). Queries made by resolvers
+/// through a `QueryResolver` are not recorded, only the ones made directly on
+/// the graph.
+///
+/// With the `serde` feature enabled the log can be serialized, which makes it
+/// easy to attach to a bug report and turn into a deterministic test with
+/// [`replay`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct QueryLog<Q> {
+    queries: Vec<Q>,
+}
+
+impl<Q> Default for QueryLog<Q> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<Q> QueryLog<Q> {
+    pub fn new() -> Self {
+        Self {
+            queries: Vec::new(),
+        }
+    }
+
+    pub fn queries(&self) -> &[Q] {
+        &self.queries
+    }
+
+    pub fn len(&self) -> usize {
+        self.queries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queries.is_empty()
+    }
+
+    pub(crate) fn push(&mut self, q: Q) {
+        self.queries.push(q);
+    }
+}
+
+impl<Q> FromIterator<Q> for QueryLog<Q> {
+    fn from_iter<T: IntoIterator<Item = Q>>(iter: T) -> Self {
+        Self {
+            queries: iter.into_iter().collect(),
+        }
+    }
+}
+
+impl<Q> IntoIterator for QueryLog<Q> {
+    type Item = Q;
+    type IntoIter = std::vec::IntoIter<Q>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.queries.into_iter()
+    }
+}
+
+/// Replays every query in the log against the graph, in the order they were
+/// originally made, and returns their results in the same order.
+pub fn replay<Q, R>(log: &QueryLog<Q>, graph: &Arc<Graph<Q, R>>) -> Vec<R>
+where
+    Q: Clone + Eq + Hash + Send + Sync,
+    R: Clone + Send + Sync,
+{
+    log.queries()
+        .iter()
+        .map(|q| graph.query(q.clone()))
+        .collect()
+}
@@ -0,0 +1,142 @@
+use std::{hash::Hash, sync::atomic::Ordering};
+
+use hashbrown::HashSet;
+
+use crate::{Graph, Slot};
+
+impl<Q: Clone + Eq + Hash + Send + Sync, R: Send + Sync> Graph<Q, R> {
+    /// Records an access to the slot (only when the graph has a maximum number
+    /// of nodes, since the shared clock is a point of contention otherwise),
+    /// and its access stats with the `access_stats` feature.
+    pub(crate) fn touch(&self, slot: &Slot<Q, R>) {
+        #[cfg(feature = "access_stats")]
+        self.record_access(slot);
+
+        if self.config.max_nodes.is_some() {
+            let tick = self.clock.fetch_add(1, Ordering::Relaxed);
+            slot.accessed.store(tick, Ordering::Relaxed);
+        }
+    }
+
+    /// Evicts the least recently accessed nodes of this iteration if there are
+    /// more than the maximum number of nodes. It evicts down to 90% of the
+    /// maximum, so that evictions are batched instead of happening on every
+    /// resolution.
+    ///
+    /// Only resolved nodes are evicted. A node that isn't resolved is either
+    /// being resolved (and so might be on the path of a resolution) or about to
+    /// be, so it's never evicted. Neither are pinned nodes (see `Graph::pin`).
+    pub(crate) fn evict_lru(&self) {
+        let Some(max_nodes) = self.config.max_nodes else {
+            return;
+        };
+
+        if self.new.len() <= max_nodes {
+            return;
+        }
+
+        // Only one thread evicts at a time, the others just keep resolving.
+        let Some(_evicting) = self.evicting.try_lock() else {
+            return;
+        };
+
+        let pinned = self.pinned.read();
+
+        let mut resolved = self
+            .new
+            .iter()
+            .filter(|(q, slot)| slot.get().is_some() && !pinned.contains(q))
+            .map(|(q, slot)| (slot.accessed.load(Ordering::Relaxed), q))
+            .collect::<Vec<_>>();
+
+        drop(pinned);
+
+        let target = max_nodes - max_nodes / 10;
+        let excess = self.new.len().saturating_sub(target).min(resolved.len());
+
+        if excess == 0 {
+            return;
+        }
+
+        resolved.select_nth_unstable_by_key(excess - 1, |(accessed, _)| *accessed);
+
+        let mut evicted = resolved
+            .into_iter()
+            .take(excess)
+            .map(|(_, q)| q)
+            .collect::<HashSet<_>>();
+
+        // A node may have been accessed since the snapshot, but it's still
+        // resolved (nodes are never unresolved), so evicting it is safe.
+        self.new.retain(|q, _| !evicted.contains(q));
+        self.generation.fetch_add(1, Ordering::Release);
+        self.retain_projections(|q| !evicted.contains(q));
+
+        if let Some(on_evict) = &self.config.on_evict {
+            evicted.drain().for_each(|q| on_evict(&q));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use parking_lot::Mutex;
+
+    use crate::{GraphBuilder, QueryResolver, ResolveQuery};
+
+    /// Resolves every query to itself, counting the resolutions.
+    #[derive(Clone, Default)]
+    struct Identity {
+        resolved: Arc<Mutex<Vec<u32>>>,
+    }
+
+    impl ResolveQuery<u32, u32> for Identity {
+        fn resolve(&self, q: u32, _resolve: Arc<QueryResolver<u32, u32>>) -> u32 {
+            self.resolved.lock().push(q);
+            q
+        }
+    }
+
+    #[test]
+    fn least_recently_accessed_nodes_are_evicted() {
+        let resolver = Identity::default();
+        let evicted = Arc::new(Mutex::new(Vec::new()));
+
+        let graph = GraphBuilder::new()
+            .max_nodes(10)
+            .on_evict({
+                let evicted = evicted.clone();
+                move |q: &u32| evicted.lock().push(*q)
+            })
+            .build(resolver.clone());
+
+        for q in 0..10 {
+            graph.query(q);
+        }
+
+        for q in 0..5 {
+            graph.query(q);
+        }
+
+        assert!(evicted.lock().is_empty());
+
+        // Going over the maximum evicts down to 9 nodes, starting with the
+        // least recently accessed.
+        graph.query(10);
+
+        let mut evicted = evicted.lock().clone();
+        evicted.sort_unstable();
+
+        assert_eq!(evicted, [5, 6]);
+        assert_eq!(graph.new.len(), 9);
+
+        // Evicted nodes are simply resolved again.
+        resolver.resolved.lock().clear();
+
+        assert_eq!(graph.query(5), 5);
+        assert_eq!(graph.query(0), 0);
+        assert_eq!(*resolver.resolved.lock(), [5]);
+    }
+}
@@ -1,13 +1,70 @@
+#[cfg(feature = "contention_stats")]
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::{fmt::Debug, hash::Hash};
 
 use ahash::RandomState;
 use hashbrown::HashMap;
-use parking_lot::RwLock;
+use parking_lot::{MappedRwLockWriteGuard, RwLock, RwLockWriteGuard};
+use rayon::prelude::{IntoParallelRefIterator, ParallelIterator};
 
+/// A `ConcurrentMap` is a hash map split into shards, each behind its own
+/// `RwLock`, so that threads working on different keys rarely contend. It's the
+/// map the `Graph` stores its nodes in.
 pub struct ConcurrentMap<K, V> {
     shards: Box<[RwLock<HashMap<K, V, RandomState>>]>,
     num_shards: usize,
     hasher: RandomState,
+    #[cfg(feature = "contention_stats")]
+    contention: Contention,
+}
+
+/// How often the shard locks of a `ConcurrentMap` were contended (see
+/// `ConcurrentMap::contention_stats`).
+#[cfg(feature = "contention_stats")]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ContentionStats {
+    /// The number of times a shard lock was acquired.
+    pub acquisitions: u64,
+    /// The number of those acquisitions that had to wait for the lock, because
+    /// another thread was holding it.
+    pub contended: u64,
+}
+
+#[cfg(feature = "contention_stats")]
+impl ContentionStats {
+    /// The fraction of acquisitions that were contended (`0.0` if the map was
+    /// never locked). If it's high, the map needs more shards.
+    pub fn contention_rate(&self) -> f64 {
+        if self.acquisitions == 0 {
+            0.0
+        } else {
+            self.contended as f64 / self.acquisitions as f64
+        }
+    }
+}
+
+#[cfg(feature = "contention_stats")]
+#[derive(Default)]
+struct Contention {
+    acquisitions: AtomicU64,
+    contended: AtomicU64,
+}
+
+#[cfg(feature = "contention_stats")]
+impl Contention {
+    /// Acquires a lock with `try_lock` first, so that a failed attempt (the
+    /// lock being held) can be counted before blocking with `lock`.
+    fn acquire<G>(&self, try_lock: impl FnOnce() -> Option<G>, lock: impl FnOnce() -> G) -> G {
+        self.acquisitions.fetch_add(1, Ordering::Relaxed);
+
+        match try_lock() {
+            Some(guard) => guard,
+            None => {
+                self.contended.fetch_add(1, Ordering::Relaxed);
+                lock()
+            }
+        }
+    }
 }
 
 impl<K: Eq + Hash, V: Clone> Default for ConcurrentMap<K, V> {
@@ -22,7 +79,6 @@ impl<K: Debug + Clone + Eq + Hash, V: Debug + Clone> Debug for ConcurrentMap<K,
             .shards
             .iter()
             .flat_map(|shard| shard.read().clone())
-            .map(|(k, v)| (k, v))
             .collect::<HashMap<_, _>>();
 
         f.debug_map().entries(debug_map.iter()).finish()
@@ -56,6 +112,8 @@ impl<K: Eq + Hash, V: Clone> ConcurrentMap<K, V> {
                 .collect::<Box<_>>(),
             num_shards,
             hasher: RandomState::default(),
+            #[cfg(feature = "contention_stats")]
+            contention: Contention::default(),
         }
     }
 
@@ -70,27 +128,49 @@ impl<K: Eq + Hash, V: Clone> ConcurrentMap<K, V> {
     unsafe fn get_read_shard(
         &self,
         idx: usize,
-    ) -> parking_lot::lock_api::RwLockReadGuard<parking_lot::RawRwLock, HashMap<K, V, RandomState>>
-    {
-        self.shards.get_unchecked(idx).read()
+    ) -> parking_lot::lock_api::RwLockReadGuard<
+        '_,
+        parking_lot::RawRwLock,
+        HashMap<K, V, RandomState>,
+    > {
+        let shard = self.shards.get_unchecked(idx);
+
+        #[cfg(feature = "contention_stats")]
+        return self
+            .contention
+            .acquire(|| shard.try_read(), || shard.read());
+
+        #[cfg(not(feature = "contention_stats"))]
+        shard.read()
     }
 
     unsafe fn get_write_shard(
         &self,
         idx: usize,
-    ) -> parking_lot::lock_api::RwLockWriteGuard<parking_lot::RawRwLock, HashMap<K, V, RandomState>>
-    {
-        self.shards.get_unchecked(idx).write()
+    ) -> parking_lot::lock_api::RwLockWriteGuard<
+        '_,
+        parking_lot::RawRwLock,
+        HashMap<K, V, RandomState>,
+    > {
+        let shard = self.shards.get_unchecked(idx);
+
+        #[cfg(feature = "contention_stats")]
+        return self
+            .contention
+            .acquire(|| shard.try_write(), || shard.write());
+
+        #[cfg(not(feature = "contention_stats"))]
+        shard.write()
     }
 
-    // pub fn insert(&self, key: K, value: V) {
-    //     let hash = self.hash(&key);
-    //     let idx = self.determine_shard(hash);
+    pub fn insert(&self, key: K, value: V) {
+        let hash = self.hash(&key);
+        let idx = self.determine_shard(hash);
 
-    //     let mut shard = unsafe { self.get_write_shard(idx) };
+        let mut shard = unsafe { self.get_write_shard(idx) };
 
-    //     shard.insert(key, value);
-    // }
+        shard.insert(key, value);
+    }
 
     pub fn get(&self, key: &K) -> Option<V> {
         let hash = self.hash(key);
@@ -130,4 +210,299 @@ impl<K: Eq + Hash, V: Clone> ConcurrentMap<K, V> {
         shard.insert(key, result.clone());
         result
     }
+
+    /// Like `get_or_insert`, but takes the key by reference and hands it to
+    /// `value`. The key is only cloned when the entry is inserted, which saves
+    /// a clone of expensive keys whenever the entry already exists.
+    pub fn get_or_insert_with_key<F: FnOnce(&K) -> V>(&self, key: &K, value: F) -> V
+    where
+        K: Clone,
+    {
+        let hash = self.hash(key);
+        let idx = self.determine_shard(hash);
+
+        // First, read the shard with just a read-lock.
+        let result = {
+            let shard = unsafe { self.get_read_shard(idx) };
+            shard.get(key).cloned()
+        };
+
+        if let Some(result) = result {
+            return result;
+        }
+
+        let mut shard = unsafe { self.get_write_shard(idx) };
+
+        // Another thread may have won and written first.
+        if let Some(result) = shard.get(key) {
+            return result.clone();
+        }
+
+        let result = value(key);
+        shard.insert(key.clone(), result.clone());
+        result
+    }
+
+    /// Gets the entry of the key for in-place manipulation, e.g. to update the
+    /// bookkeeping of a value (like an access timestamp) atomically, without a
+    /// separate `get` and `insert`.
+    ///
+    /// The key's shard is write-locked for as long as the entry (or the guard
+    /// it hands out) lives, which blocks every other access to the keys of that
+    /// shard. Accessing the map again on the same thread while holding it (even
+    /// with another key, which may be in the same shard) deadlocks, so keep the
+    /// entry short lived and don't resolve queries while holding it.
+    pub fn entry(&self, key: K) -> Entry<'_, K, V> {
+        let hash = self.hash(&key);
+        let idx = self.determine_shard(hash);
+
+        Entry {
+            shard: unsafe { self.get_write_shard(idx) },
+            key,
+        }
+    }
+
+    /// Returns how often the shard locks taken by `get`, `insert`, the
+    /// `get_or_insert` variants, `retain` and `extend` were contended so far.
+    #[cfg(feature = "contention_stats")]
+    pub fn contention_stats(&self) -> ContentionStats {
+        ContentionStats {
+            acquisitions: self.contention.acquisitions.load(Ordering::Relaxed),
+            contended: self.contention.contended.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Returns the number of entries in the map.
+    pub fn len(&self) -> usize {
+        self.shards.iter().map(|shard| shard.read().len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Iterates over a snapshot of the entries. Each shard is only read-locked
+    /// long enough to clone its entries, so no lock is held while the iterator
+    /// is being consumed (and the map can be freely used meanwhile).
+    pub fn iter(&self) -> impl Iterator<Item = (K, V)> + '_
+    where
+        K: Clone,
+    {
+        self.shards.iter().flat_map(|shard| {
+            shard
+                .read()
+                .iter()
+                .map(|(key, value)| (key.clone(), value.clone()))
+                .collect::<Vec<_>>()
+        })
+    }
+
+    /// Like `iter`, but the shards are snapshotted (and the entries consumed)
+    /// in parallel. No lock is held while the entries are being consumed.
+    pub fn par_iter(&self) -> impl ParallelIterator<Item = (K, V)> + '_
+    where
+        K: Clone + Send + Sync,
+        V: Send + Sync,
+    {
+        self.shards.par_iter().flat_map_iter(|shard| {
+            shard
+                .read()
+                .iter()
+                .map(|(key, value)| (key.clone(), value.clone()))
+                .collect::<Vec<_>>()
+        })
+    }
+
+    /// Removes every entry for which `f` returns `false`. Each shard is
+    /// write-locked while `f` is called for its entries, so `f` must not access
+    /// the map.
+    pub fn retain<F: FnMut(&K, &mut V) -> bool>(&self, mut f: F) {
+        for idx in 0..self.num_shards {
+            let mut shard = unsafe { self.get_write_shard(idx) };
+            shard.retain(|key, value| f(key, value));
+        }
+    }
+
+    /// Inserts every entry (replacing existing ones), taking each shard's
+    /// write-lock only once. This is much faster than inserting entries one at
+    /// a time when seeding a map with a lot of entries.
+    pub fn extend<I: IntoIterator<Item = (K, V)>>(&self, iter: I) {
+        let mut batches = (0..self.num_shards).map(|_| Vec::new()).collect::<Vec<_>>();
+
+        for (key, value) in iter {
+            let hash = self.hash(&key);
+            let idx = self.determine_shard(hash);
+
+            batches[idx].push((key, value));
+        }
+
+        for (idx, batch) in batches.into_iter().enumerate() {
+            if batch.is_empty() {
+                continue;
+            }
+
+            let mut shard = unsafe { self.get_write_shard(idx) };
+            shard.extend(batch);
+        }
+    }
+}
+
+/// An entry of a `ConcurrentMap`, which holds its shard's write-lock (see
+/// `ConcurrentMap::entry`).
+pub struct Entry<'a, K, V> {
+    shard: RwLockWriteGuard<'a, HashMap<K, V, RandomState>>,
+    key: K,
+}
+
+impl<'a, K: Eq + Hash, V> Entry<'a, K, V> {
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    /// Whether the map has a value for the key.
+    pub fn is_occupied(&self) -> bool {
+        self.shard.contains_key(&self.key)
+    }
+
+    /// Calls `f` with the value if there is one.
+    pub fn and_modify(mut self, f: impl FnOnce(&mut V)) -> Self {
+        if let Some(value) = self.shard.get_mut(&self.key) {
+            f(value);
+        }
+
+        self
+    }
+
+    /// Returns the value, or `None` if there isn't one. The shard stays locked
+    /// until the guard is dropped.
+    pub fn get_mut(self) -> Option<MappedRwLockWriteGuard<'a, V>> {
+        let Self { shard, key } = self;
+
+        RwLockWriteGuard::try_map(shard, |shard| shard.get_mut(&key)).ok()
+    }
+
+    /// Returns the value, inserting the one returned by `value` first if there
+    /// isn't one. The shard stays locked until the guard is dropped.
+    pub fn or_insert_with(self, value: impl FnOnce() -> V) -> MappedRwLockWriteGuard<'a, V> {
+        let Self { shard, key } = self;
+
+        RwLockWriteGuard::map(shard, |shard| shard.entry(key).or_insert_with(value))
+    }
+}
+
+impl<K: Eq + Hash, V: Clone> Extend<(K, V)> for ConcurrentMap<K, V> {
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) {
+        ConcurrentMap::extend(self, iter);
+    }
+}
+
+/// A `NodeStore` is a concurrent map the `Graph` can store its nodes in,
+/// instead of the default `ConcurrentMap` (see `GraphBuilder::node_store`).
+/// This allows alternate backends, like persistent maps or a store that keeps
+/// most of a huge cache on disk.
+///
+/// Every method can be called from any number of threads at once. The values
+/// are cheap to clone (they're reference counted), and a store must hand out
+/// clones of the same value for a key until it's removed (or the graph would
+/// resolve the query more than once).
+pub trait NodeStore<K, V>: Send + Sync {
+    fn get(&self, key: &K) -> Option<V>;
+
+    /// Gets the value of the key, inserting the value returned by `value`
+    /// first if there isn't one. When several threads race to insert the same
+    /// key, they must all get the value of the one that won.
+    fn get_or_insert(&self, key: K, value: &mut dyn FnMut() -> V) -> V;
+
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Iterates over the entries. The iterator shouldn't hold any locks the
+    /// other methods need, since the graph may use the store while iterating.
+    fn iter(&self) -> Box<dyn Iterator<Item = (K, V)> + '_>;
+
+    /// Removes every entry for which `f` returns `false`.
+    fn retain(&self, f: &mut dyn FnMut(&K, &mut V) -> bool);
+
+    /// Inserts every entry, replacing existing ones.
+    fn extend(&self, entries: Vec<(K, V)>);
+}
+
+impl<K: Clone + Eq + Hash + Send + Sync, V: Clone + Send + Sync> NodeStore<K, V>
+    for ConcurrentMap<K, V>
+{
+    fn get(&self, key: &K) -> Option<V> {
+        ConcurrentMap::get(self, key)
+    }
+
+    fn get_or_insert(&self, key: K, value: &mut dyn FnMut() -> V) -> V {
+        ConcurrentMap::get_or_insert(self, key, value)
+    }
+
+    fn len(&self) -> usize {
+        ConcurrentMap::len(self)
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = (K, V)> + '_> {
+        Box::new(ConcurrentMap::iter(self))
+    }
+
+    fn retain(&self, f: &mut dyn FnMut(&K, &mut V) -> bool) {
+        ConcurrentMap::retain(self, f)
+    }
+
+    fn extend(&self, entries: Vec<(K, V)>) {
+        ConcurrentMap::extend(self, entries)
+    }
+}
+
+/// A `NodeStoreFamily` names a generic `NodeStore` without naming its key and
+/// value types, since the values the graph stores are private to it.
+///
+/// ```ignore
+/// struct MyStoreFamily;
+///
+/// impl NodeStoreFamily for MyStoreFamily {
+///     type Store<K: Clone + Eq + Hash + Send + Sync + 'static, V: Clone + Send + Sync + 'static> =
+///         MyStore<K, V>;
+/// }
+/// ```
+pub trait NodeStoreFamily {
+    type Store<K: Clone + Eq + Hash + Send + Sync + 'static, V: Clone + Send + Sync + 'static>: NodeStore<K, V>
+        + Default
+        + 'static;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+
+    #[test]
+    fn values_stay_put_while_the_map_grows() {
+        let map = ConcurrentMap::new();
+        let first = map.get_or_insert_with_key(&0, |key| Arc::new(key.to_string()));
+
+        // Grow every shard (reallocating their tables many times over).
+        for key in 1..100_000 {
+            map.get_or_insert_with_key(&key, |key| Arc::new(key.to_string()));
+        }
+
+        assert_eq!(*first, "0");
+        assert!(Arc::ptr_eq(&first, &map.get(&0).unwrap()));
+        assert!(Arc::ptr_eq(
+            &first,
+            &map.get_or_insert_with_key(&0, |_| unreachable!())
+        ));
+
+        // Modifying an entry in place replaces the value of that key only.
+        map.entry(1)
+            .and_modify(|value| *value = Arc::new("one".to_string()));
+
+        assert_eq!(*map.get(&1).unwrap(), "one");
+        assert!(Arc::ptr_eq(&first, &map.get(&0).unwrap()));
+    }
 }
@@ -0,0 +1,68 @@
+use std::{hash::Hash, sync::Arc};
+
+use crate::{map::ConcurrentMap, NodeCell};
+
+/// A `Memo` is a concurrent memoization cache: every key's value is computed
+/// once (by whichever thread gets to it first) and cloned out afterwards. It's
+/// what's left of a `Graph` without increments, dependencies or validation,
+/// for users that don't need them.
+///
+/// ```
+/// use query_graph::Memo;
+///
+/// let memo = Memo::new();
+///
+/// // A computation that can fail memoizes its `Result` (or `Option`).
+/// let port = memo.get_or_compute("8080".to_owned(), |text| text.parse::<u16>().ok());
+/// assert_eq!(port, Some(8080));
+///
+/// // The next time, the value is cloned out without computing it again.
+/// let port = memo.get_or_compute("8080".to_owned(), |_| unreachable!());
+/// assert_eq!(port, Some(8080));
+/// ```
+pub struct Memo<K, V> {
+    cells: ConcurrentMap<K, Arc<NodeCell<V>>>,
+}
+
+impl<K: Clone + Eq + Hash, V: Clone> Default for Memo<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: Clone + Eq + Hash, V: Clone> Memo<K, V> {
+    pub fn new() -> Self {
+        Self {
+            cells: ConcurrentMap::new(),
+        }
+    }
+
+    /// Returns the value of the key, computing it with `f` first if it wasn't
+    /// computed yet. Threads that ask for a key while it's being computed wait
+    /// for it, so `f` runs at most once per key (unless it panics, in which
+    /// case the next caller computes it).
+    ///
+    /// `f` can use the memo for other keys, but asking for the key it's
+    /// computing (directly or not) deadlocks, since there's no cycle detection.
+    pub fn get_or_compute(&self, key: K, f: impl FnOnce(&K) -> V) -> V {
+        let cell = self
+            .cells
+            .get_or_insert_with_key(&key, |_| Arc::new(NodeCell::new()));
+
+        cell.get_or_init(|| f(&key)).clone()
+    }
+
+    /// Returns the value of the key if it was computed, without computing it.
+    pub fn get(&self, key: &K) -> Option<V> {
+        self.cells.get(key)?.get().cloned()
+    }
+
+    /// The number of keys computed (or being computed).
+    pub fn len(&self) -> usize {
+        self.cells.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cells.is_empty()
+    }
+}
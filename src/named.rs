@@ -0,0 +1,37 @@
+use std::{hash::Hash, sync::Arc};
+
+use crate::Graph;
+
+/// A `NamedQuery` can be written as (and parsed from) a string, so that
+/// queries can be looked up by name, like in a REPL or debugger over a live
+/// graph (see `Graph::query_by_name`).
+///
+/// `parse` should accept every name `name` returns, so that the names listed
+/// by `Graph::query_names` can be queried again.
+pub trait NamedQuery: Sized {
+    fn name(&self) -> String;
+
+    fn parse(name: &str) -> Option<Self>;
+}
+
+impl<Q: NamedQuery + Clone + Eq + Hash + Send + Sync, R: Clone + Send + Sync> Graph<Q, R> {
+    /// Parses the query from its name and queries it, or returns `None` if the
+    /// name doesn't parse.
+    pub fn query_by_name(self: &Arc<Self>, name: &str) -> Option<R> {
+        Q::parse(name).map(|q| self.query(q))
+    }
+
+    /// Returns the names of the queries resolved in this iteration so far,
+    /// sorted.
+    pub fn query_names(&self) -> Vec<String> {
+        let mut names = self
+            .new
+            .iter()
+            .filter(|(_, slot)| slot.get().is_some())
+            .map(|(q, _)| q.name())
+            .collect::<Vec<_>>();
+
+        names.sort_unstable();
+        names
+    }
+}
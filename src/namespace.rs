@@ -0,0 +1,39 @@
+use std::{hash::Hash, sync::Arc};
+
+use hashbrown::{HashMap, HashSet};
+use parking_lot::Mutex;
+
+use crate::Graph;
+
+/// Maps every namespace to the queries resolved in it during an iteration.
+pub(crate) type NamespaceIndex<Q> = Arc<Mutex<HashMap<String, HashSet<Q>>>>;
+
+impl<Q: Clone + Eq + Hash + Send + Sync, R: Send + Sync> Graph<Q, R> {
+    /// Invalidates every query in the namespace (see `ResolveQuery::namespace`)
+    /// at once, like calling `invalidate` with each of them. This covers the
+    /// queries resolved in the previous iteration (which are the ones that
+    /// would otherwise be reused) and the ones resolved in this iteration so
+    /// far (which aren't affected, like with `invalidate`).
+    pub fn invalidate_namespace(&self, namespace: &str) {
+        let mut invalidated = self.invalidated.write();
+
+        for index in [&self.old_namespaces, &self.namespaces] {
+            if let Some(queries) = index.lock().get(namespace) {
+                for q in queries {
+                    self.mark_invalidated(&mut invalidated, q.clone());
+                }
+            }
+        }
+    }
+
+    /// Records the query in the index of its namespace (if it has one).
+    pub(crate) fn record_namespace(&self, q: &Q) {
+        if let Some(namespace) = self.resolver.namespace(q) {
+            self.namespaces
+                .lock()
+                .entry(namespace)
+                .or_default()
+                .insert(q.clone());
+        }
+    }
+}
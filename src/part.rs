@@ -0,0 +1,154 @@
+use std::{
+    fmt::{self, Debug},
+    hash::Hash,
+    sync::Arc,
+};
+
+use hashbrown::HashMap;
+
+use crate::{Graph, Node, QueryResolver};
+
+/// The dependencies a node depends on only through parts of their results (see
+/// `QueryResolver::query_part`), along with the parts it read.
+pub(crate) type Parts<Q, R> = Arc<PartEdges<Q, R>>;
+
+pub(crate) type PartEdges<Q, R> = HashMap<Q, Vec<Arc<dyn PartEdge<R>>>>;
+
+/// A part of a dependency's result a node read, type erased since every part
+/// has its own type.
+pub(crate) trait PartEdge<R>: Send + Sync {
+    /// Whether the part of the result differs from the part that was read.
+    fn changed(&self, result: &R) -> bool;
+}
+
+impl<R> Debug for dyn PartEdge<R> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("PartEdge")
+    }
+}
+
+struct Part<T, F> {
+    project: F,
+    /// The part that was read.
+    value: T,
+}
+
+impl<R, T: PartialEq + Send + Sync, F: Fn(&R) -> T + Send + Sync> PartEdge<R> for Part<T, F> {
+    fn changed(&self, result: &R) -> bool {
+        (self.project)(result) != self.value
+    }
+}
+
+impl<Q: Clone + Eq + Hash + Send + Sync, R: Send + Sync> QueryResolver<Q, R> {
+    /// Queries a query but only depends on the part of its result `project`
+    /// returns (like a single entry of a large map). When the result of the
+    /// query changes, the query being resolved is only resolved again if the
+    /// part changed too (by `PartialEq`), instead of whenever anything in the
+    /// result changed.
+    ///
+    /// `project` is kept to validate the part in later iterations, so it must
+    /// be a pure function of the result. Querying the same query in full
+    /// (with `query`) makes the whole result a dependency again. Parts aren't
+    /// part of snapshots, so queries restored from one depend on the whole
+    /// result.
+    pub fn query_part<T>(&self, q: Q, project: impl Fn(&R) -> T + Send + Sync + 'static) -> T
+    where
+        T: PartialEq + Clone + Send + Sync + 'static,
+    {
+        let (result, recovered) = self.query_untracked(&q);
+        let value = project(&result);
+
+        if recovered {
+            return value;
+        }
+
+        self.parts.lock().entry(q).or_default().push(Arc::new(Part {
+            project,
+            value: value.clone(),
+        }));
+
+        value
+    }
+}
+
+impl<Q: Clone + Eq + Hash + Send + Sync, R: Send + Sync> Graph<Q, R> {
+    /// Whether the change to a dependency's node affects the node that depends
+    /// on it, which is only the case if it depends on the whole result, or any
+    /// part of it that it read changed.
+    pub(crate) fn parts_changed(&self, parts: &Parts<Q, R>, parent: &Q, node: &Node<Q, R>) -> bool {
+        match parts.get(parent) {
+            Some(parts) => {
+                let result = self.load(&node.result);
+                parts.iter().any(|part| part.changed(&result))
+            }
+            None => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use parking_lot::Mutex;
+
+    use crate::{Graph, ResolveQuery};
+
+    use super::*;
+
+    #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+    enum Query {
+        Config,
+        Entry(usize),
+        Sum,
+    }
+
+    /// `Entry(i)` only reads the `i`th entry of the config, while `Sum` reads
+    /// the whole config.
+    #[derive(Clone, Default)]
+    struct Resolver {
+        config: Arc<Mutex<Vec<u32>>>,
+        resolved: Arc<Mutex<Vec<Query>>>,
+    }
+
+    impl ResolveQuery<Query, Vec<u32>> for Resolver {
+        fn resolve(&self, q: Query, resolve: Arc<QueryResolver<Query, Vec<u32>>>) -> Vec<u32> {
+            self.resolved.lock().push(q.clone());
+
+            match q {
+                Query::Config => self.config.lock().clone(),
+                Query::Entry(i) => vec![resolve.query_part(Query::Config, move |config| config[i])],
+                Query::Sum => vec![resolve.query(Query::Config).iter().sum()],
+            }
+        }
+
+        fn is_input(&self, q: &Query) -> bool {
+            *q == Query::Config
+        }
+    }
+
+    #[test]
+    fn only_changed_parts_are_resolved_again() {
+        let resolver = Resolver::default();
+        *resolver.config.lock() = vec![1, 2];
+
+        let graph = Graph::new(resolver.clone());
+
+        assert_eq!(graph.query(Query::Entry(0)), [1]);
+        assert_eq!(graph.query(Query::Entry(1)), [2]);
+        assert_eq!(graph.query(Query::Sum), [3]);
+
+        resolver.config.lock()[1] = 5;
+        resolver.resolved.lock().clear();
+        graph.invalidate(Query::Config);
+
+        let graph = graph.increment(resolver.clone());
+
+        assert_eq!(graph.query(Query::Entry(0)), [1]);
+        assert_eq!(graph.query(Query::Entry(1)), [5]);
+        assert_eq!(graph.query(Query::Sum), [6]);
+
+        assert_eq!(
+            *resolver.resolved.lock(),
+            [Query::Config, Query::Entry(1), Query::Sum]
+        );
+    }
+}
@@ -0,0 +1,210 @@
+use std::{
+    error::Error,
+    fmt::{self, Debug, Display},
+    fs::File,
+    hash::Hash,
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::Path,
+    sync::Arc,
+};
+
+use bincode::Options;
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{Graph, GraphBuilder, ResolveQuery, Snapshot};
+
+/// Identifies a snapshot file, so that any other file fails to load instead of
+/// being decoded into garbage nodes.
+const MAGIC: &[u8; 8] = b"QGSNAPSH";
+
+/// The version of the file format, bumped whenever it (or the encoding of a
+/// snapshot) changes incompatibly.
+const VERSION: u32 = 1;
+
+/// The bincode options snapshots are encoded with.
+fn options() -> impl Options {
+    bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .allow_trailing_bytes()
+}
+
+/// The error of saving or loading a snapshot file (see
+/// `Graph::snapshot_to_file`).
+pub enum SnapshotFileError {
+    Io(io::Error),
+    /// The file isn't a snapshot file.
+    BadMagic,
+    /// The file is a snapshot file of another (incompatible) version.
+    UnsupportedVersion(u32),
+    /// The snapshot couldn't be encoded or decoded (e.g. the file was written
+    /// with other query or result types).
+    Encoding(bincode::Error),
+}
+
+impl Display for SnapshotFileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(error) => write!(f, "snapshot file io error: {}", error),
+            Self::BadMagic => write!(f, "not a snapshot file"),
+            Self::UnsupportedVersion(version) => write!(
+                f,
+                "unsupported snapshot file version {} (expected {})",
+                version, VERSION
+            ),
+            Self::Encoding(error) => write!(f, "snapshot encoding error: {}", error),
+        }
+    }
+}
+
+impl Debug for SnapshotFileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        Display::fmt(self, f)
+    }
+}
+
+impl Error for SnapshotFileError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Io(error) => Some(error),
+            Self::Encoding(error) => Some(error),
+            Self::BadMagic | Self::UnsupportedVersion(_) => None,
+        }
+    }
+}
+
+impl From<io::Error> for SnapshotFileError {
+    fn from(error: io::Error) -> Self {
+        Self::Io(error)
+    }
+}
+
+impl From<bincode::Error> for SnapshotFileError {
+    fn from(error: bincode::Error) -> Self {
+        Self::Encoding(error)
+    }
+}
+
+impl<Q, R> Graph<Q, R>
+where
+    Q: Clone + Eq + Hash + Send + Sync + Serialize,
+    R: Clone + Send + Sync + Serialize,
+{
+    /// Writes a snapshot of this iteration (see `snapshot`) to a file, encoded
+    /// with bincode after a small header (a magic number and the version of
+    /// the format), so that it can be loaded with `from_file`.
+    ///
+    /// The nodes (and their dependencies) are written sorted by the encoding of
+    /// their queries, so the same logical graph is always written to the same
+    /// bytes (e.g. for content-addressed caches), no matter the order it was
+    /// resolved in or the random state of the hasher.
+    pub fn snapshot_to_file(&self, path: impl AsRef<Path>) -> Result<(), SnapshotFileError> {
+        // A query that can't be encoded fails the snapshot when it's written
+        // anyway, so its (empty) key doesn't matter.
+        let snapshot = self
+            .snapshot()
+            .sorted_by_cached_key(|q| options().serialize(q).unwrap_or_default());
+
+        let mut writer = BufWriter::new(File::create(path)?);
+
+        writer.write_all(MAGIC)?;
+        writer.write_all(&VERSION.to_le_bytes())?;
+        options().serialize_into(&mut writer, &snapshot)?;
+
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+impl<Q, R> Graph<Q, R>
+where
+    Q: Clone + Eq + Hash + Send + Sync + DeserializeOwned,
+    R: PartialEq + Send + Sync + DeserializeOwned,
+{
+    /// Creates a graph from a snapshot file written by `snapshot_to_file` (see
+    /// `GraphBuilder::build_from_snapshot`). Fails without creating anything
+    /// if the file isn't a snapshot file of this version.
+    pub fn from_file(
+        resolver: impl ResolveQuery<Q, R> + 'static,
+        path: impl AsRef<Path>,
+    ) -> Result<Arc<Self>, SnapshotFileError> {
+        let file = File::open(path)?;
+        let len = file.metadata()?.len();
+        let mut reader = BufReader::new(file);
+
+        let mut magic = [0; MAGIC.len()];
+        let mut version = [0; 4];
+
+        // A file too short for the header isn't a snapshot file either.
+        if reader.read_exact(&mut magic).is_err() || magic != *MAGIC {
+            return Err(SnapshotFileError::BadMagic);
+        }
+
+        reader.read_exact(&mut version)?;
+        let version = u32::from_le_bytes(version);
+
+        if version != VERSION {
+            return Err(SnapshotFileError::UnsupportedVersion(version));
+        }
+
+        // Decoding is limited to the size of the file, so that a file written
+        // with other types fails instead of allocating whatever lengths it
+        // happens to decode.
+        let snapshot: Snapshot<Q, R> = options().with_limit(len).deserialize_from(reader)?;
+        Ok(GraphBuilder::new().build_from_snapshot(snapshot, resolver))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use serde::{Deserialize, Serialize};
+
+    use crate::QueryResolver;
+
+    use super::*;
+
+    /// Queries that aren't `Ord`, so only the encoding can order them.
+    #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+    enum Query {
+        Sum(u32),
+        Input(u32),
+    }
+
+    struct Resolver;
+
+    impl ResolveQuery<Query, u32> for Resolver {
+        fn resolve(&self, q: Query, resolve: Arc<QueryResolver<Query, u32>>) -> u32 {
+            match q {
+                Query::Sum(n) => (0..n).map(|i| resolve.query(Query::Input(i))).sum(),
+                Query::Input(i) => i * 2,
+            }
+        }
+    }
+
+    #[test]
+    fn snapshot_files_are_reproducible() {
+        let dir = std::env::temp_dir();
+        let first = dir.join(format!("query-graph-{}-first.snapshot", std::process::id()));
+        let second = dir.join(format!(
+            "query-graph-{}-second.snapshot",
+            std::process::id()
+        ));
+
+        // Two graphs (each with its own hasher random state), resolving the
+        // same queries in opposite orders.
+        let graph = Graph::new(Resolver);
+        (1..64).for_each(|n| _ = graph.query(Query::Sum(n)));
+        graph.snapshot_to_file(&first).unwrap();
+
+        let graph = Graph::new(Resolver);
+        (1..64).rev().for_each(|n| _ = graph.query(Query::Sum(n)));
+        graph.snapshot_to_file(&second).unwrap();
+
+        let (first_bytes, second_bytes) = (fs::read(&first).unwrap(), fs::read(&second).unwrap());
+        fs::remove_file(first).unwrap();
+        fs::remove_file(second).unwrap();
+
+        assert_eq!(first_bytes, second_bytes);
+    }
+}
@@ -0,0 +1,32 @@
+use std::{hash::Hash, sync::Arc};
+
+use hashbrown::HashSet;
+use parking_lot::RwLock;
+
+use crate::Graph;
+
+/// The pinned queries (see `Graph::pin`), shared by every iteration
+/// incremented from the same graph.
+pub(crate) type Pinned<Q> = Arc<RwLock<HashSet<Q>>>;
+
+impl<Q: Clone + Eq + Hash + Send + Sync, R: Send + Sync> Graph<Q, R> {
+    /// Pins the query, so that its node is never evicted: neither by `retain`
+    /// nor to stay under `GraphBuilder::max_nodes`. This is for results an
+    /// application depends on indefinitely (like a root semantic model), which
+    /// memory pressure should never drop. The query stays pinned in every
+    /// iteration incremented from this one, until it's unpinned.
+    ///
+    /// Pinning only protects the query's own node, not its dependencies.
+    pub fn pin(&self, q: Q) {
+        self.pinned.write().insert(q);
+    }
+
+    /// Unpins the query (see `pin`), so that it can be evicted again.
+    pub fn unpin(&self, q: &Q) {
+        self.pinned.write().remove(q);
+    }
+
+    pub fn is_pinned(&self, q: &Q) -> bool {
+        self.pinned.read().contains(q)
+    }
+}
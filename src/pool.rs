@@ -0,0 +1,135 @@
+use std::{collections::BTreeMap, hash::Hash, sync::Arc};
+
+use hashbrown::HashSet;
+use parking_lot::Mutex;
+
+use crate::{store::NodeMap, Graph, NodeRef};
+
+/// A pool of cleared edge sets (see `GraphBuilder::pool_edge_sets`), shared by
+/// every iteration of a graph.
+pub(crate) struct EdgePool<Q, R> {
+    /// The pooled sets by their capacity, so that a resolution can take the
+    /// smallest one that fits its edges.
+    sets: Mutex<BTreeMap<usize, Vec<HashSet<Q>>>>,
+    capacity: usize,
+    /// Recycles the edge sets of a map that's being dropped. It's captured by
+    /// the `GraphBuilder`, so that a `Graph` can recycle its old map when it's
+    /// dropped (which can't require any bounds).
+    recycle: fn(&Self, &mut NodeMap<Q, NodeRef<Q, R>>),
+}
+
+impl<Q: Clone + Eq + Hash + Send + Sync, R: Send + Sync> EdgePool<Q, R> {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            sets: Mutex::new(BTreeMap::new()),
+            capacity,
+            recycle: Self::recycle,
+        }
+    }
+
+    /// The number of pooled sets.
+    fn len(sets: &BTreeMap<usize, Vec<HashSet<Q>>>) -> usize {
+        sets.values().map(Vec::len).sum()
+    }
+
+    /// Takes the smallest pooled set with room for at least `capacity` edges.
+    /// Handing out a larger set than needed would keep its memory alive (and
+    /// make it slower to iterate) for as long as the node, and pass it on when
+    /// the node is recycled.
+    fn take(&self, capacity: usize) -> Option<HashSet<Q>> {
+        let mut sets = self.sets.lock();
+        let (&fit, fitting) = sets.range_mut(capacity..).next()?;
+        let edges = fitting.pop();
+
+        if fitting.is_empty() {
+            sets.remove(&fit);
+        }
+
+        edges
+    }
+
+    /// Takes the edge sets of the nodes out of the map (up to the capacity of
+    /// the pool), clears them and adds them to the pool. Only the edge sets no
+    /// other node shares (reused nodes share them with their old nodes) can be
+    /// recycled, and only nodes no one else holds are taken out. Empty sets
+    /// (which never allocated) aren't worth pooling.
+    fn recycle(&self, map: &mut NodeMap<Q, NodeRef<Q, R>>) {
+        let room = self.capacity.saturating_sub(Self::len(&self.sets.lock()));
+        let mut recycled = Vec::new();
+
+        map.retain(|_, slot| {
+            if recycled.len() >= room {
+                return true;
+            }
+
+            let Some(node) = Arc::get_mut(slot).and_then(|slot| slot.cell.take()) else {
+                return true;
+            };
+
+            if let Ok(mut edges) = Arc::try_unwrap(node.edges_from) {
+                if edges.capacity() > 0 {
+                    edges.clear();
+                    recycled.push(edges);
+                }
+            }
+
+            false
+        });
+
+        let mut sets = self.sets.lock();
+        let room = self.capacity.saturating_sub(Self::len(&sets));
+
+        for edges in recycled.into_iter().take(room) {
+            sets.entry(edges.capacity()).or_default().push(edges);
+        }
+    }
+}
+
+impl<Q: Clone + Eq + Hash + Send + Sync, R: Send + Sync> Graph<Q, R> {
+    /// Creates an empty edge set for a resolution (with room for at least
+    /// `capacity` edges), reusing a pooled one if one fits.
+    pub(crate) fn new_edge_set(&self, capacity: usize) -> HashSet<Q> {
+        self.config
+            .edge_pool
+            .as_ref()
+            .and_then(|pool| pool.take(capacity))
+            .unwrap_or_else(|| HashSet::with_capacity(capacity))
+    }
+}
+
+impl<Q, R> Drop for Graph<Q, R> {
+    /// Recycles the edge sets of the old map if this iteration is the last one
+    /// holding it (the previous iteration was already dropped).
+    fn drop(&mut self) {
+        if let Some(pool) = &self.config.edge_pool {
+            if let Some(old) = Arc::get_mut(&mut self.old) {
+                (pool.recycle)(pool, old);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn takes_the_smallest_set_that_fits() {
+        let pool = EdgePool::<u32, u32>::new(8);
+
+        for capacity in [3, 100, 28] {
+            let edges = HashSet::<u32>::with_capacity(capacity);
+            let mut sets = pool.sets.lock();
+            sets.entry(edges.capacity()).or_default().push(edges);
+        }
+
+        let capacity = |edges: Option<HashSet<u32>>| edges.map(|edges| edges.capacity());
+
+        assert_eq!(capacity(pool.take(10)), Some(28));
+        assert_eq!(capacity(pool.take(200)), None);
+        assert_eq!(capacity(pool.take(0)), Some(3));
+        assert!(capacity(pool.take(0)).is_some_and(|capacity| capacity >= 100));
+        assert_eq!(capacity(pool.take(0)), None);
+        assert!(pool.sets.lock().is_empty());
+    }
+}
@@ -0,0 +1,10 @@
+/// A `ProgressSink` receives progress reports while a graph resolves queries,
+/// so that long cold builds can give some feedback (like a progress bar).
+///
+/// Reports are throttled (see `GraphBuilder::progress`) and can come from any
+/// of the threads resolving queries, so implementations must be cheap and
+/// thread-safe.
+pub trait ProgressSink: Send + Sync {
+    /// Called with the number of queries resolved so far in the iteration.
+    fn resolved(&self, resolved: usize);
+}
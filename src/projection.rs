@@ -0,0 +1,117 @@
+use std::{any::Any, hash::Hash, sync::Arc};
+
+use crate::{compress::Stored, cycle::Path, map::ConcurrentMap, Graph};
+
+/// The cached projections of query results (see `Graph::derive`), shared by
+/// every iteration incremented from the same graph.
+pub(crate) type Projections<Q, R> = Arc<ConcurrentMap<(Q, &'static str), Arc<Projection<R>>>>;
+
+pub(crate) struct Projection<R> {
+    /// The result the projection was computed from.
+    source: Stored<R>,
+    value: Arc<dyn Any + Send + Sync>,
+}
+
+impl<Q: Clone + Eq + Hash + Send + Sync, R: Send + Sync> Graph<Q, R> {
+    /// Queries the query and returns `f` applied to its result, caching the
+    /// projection under `key`. The projection is only computed again when the
+    /// result of the query changes (according to the change detector), so a
+    /// transform that's applied to the same query everywhere doesn't have to
+    /// be a query of its own to be cached.
+    ///
+    /// Projections are cached across iterations, as long as the query is
+    /// resolved in each of them (the projections of a query that's dropped by
+    /// `retain` or evicted, or that isn't queried in an iteration, are dropped
+    /// with it). Every projection of a query must have its own key (with a
+    /// single type `T`), otherwise they keep replacing each other.
+    pub fn derive<T: Clone + Send + Sync + 'static>(
+        self: &Arc<Self>,
+        q: Q,
+        key: &'static str,
+        f: impl FnOnce(&R) -> T,
+    ) -> T {
+        self.log_query(&q);
+
+        let slot = self.get_node(&q);
+        let node = self.get_or_resolve(&slot, q.clone(), &Path::default());
+
+        if let Some(projection) = self.projections.get(&(q.clone(), key)) {
+            if !self.changed(&projection.source, &node.result) {
+                if let Some(value) = projection.value.downcast_ref::<T>() {
+                    return value.clone();
+                }
+            }
+        }
+
+        let value = f(&self.load(&node.result));
+
+        self.projections.insert(
+            (q, key),
+            Arc::new(Projection {
+                source: node.result.clone(),
+                value: Arc::new(value.clone()),
+            }),
+        );
+
+        value
+    }
+
+    /// Drops the projections of the queries for which `keep` returns `false`
+    /// (like queries whose nodes were dropped), so that projections don't
+    /// outlive the nodes they were computed from.
+    pub(crate) fn retain_projections(&self, mut keep: impl FnMut(&Q) -> bool) {
+        self.projections.retain(|(q, _), _| keep(q));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{GraphBuilder, QueryResolver, ResolveQuery};
+
+    use super::*;
+
+    struct Resolver;
+
+    impl ResolveQuery<u32, u32> for Resolver {
+        fn resolve(&self, q: u32, _resolve: Arc<QueryResolver<u32, u32>>) -> u32 {
+            q * 2
+        }
+    }
+
+    #[test]
+    fn projections_are_dropped_with_their_nodes() {
+        let graph = GraphBuilder::new().max_nodes(10).build(Resolver);
+
+        for q in 0..8 {
+            assert_eq!(graph.derive(q, "plus one", |result| result + 1), q * 2 + 1);
+        }
+
+        assert_eq!(graph.projections.len(), 8);
+
+        // Dropped nodes drop their projections.
+        graph.retain(|q| *q >= 4);
+        assert_eq!(graph.projections.len(), 4);
+
+        // So do evicted ones (down to 90% of the maximum).
+        for q in 8..16 {
+            graph.derive(q, "plus one", |result| result + 1);
+        }
+
+        assert!(graph.new.len() < 12);
+        assert_eq!(graph.projections.len(), graph.new.len());
+
+        // Only the projections of queries resolved in this iteration are
+        // carried over to the next one, where they're reused as is.
+        let next = graph.increment(Resolver);
+        assert_eq!(next.projections.len(), graph.new.len());
+
+        let q = graph.new.iter().next().unwrap().0;
+        assert_eq!(
+            next.derive(q, "plus one", |_| -> u32 { unreachable!() }),
+            q * 2 + 1
+        );
+
+        let next = next.increment(Resolver);
+        assert_eq!(next.projections.len(), 1);
+    }
+}
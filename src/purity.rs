@@ -0,0 +1,71 @@
+use std::{collections::VecDeque, hash::Hash};
+
+use hashbrown::HashMap;
+
+use crate::Graph;
+
+/// A query that's supposed to be pure but transitively depends on a volatile
+/// query (see `ResolveQuery::is_volatile`), which makes its memoization
+/// unsound: its old result is reused whenever its other dependencies didn't
+/// change, even though the volatile query may have.
+#[derive(Debug, Clone)]
+pub struct PurityViolation<Q> {
+    /// The (non-volatile) query that depends on a volatile one.
+    pub query: Q,
+    /// The volatile query it depends on.
+    pub volatile: Q,
+    /// The chain of dependencies from `query` to `volatile` (inclusive).
+    pub chain: Vec<Q>,
+}
+
+impl<Q: Clone + Eq + Hash + Send + Sync, R: Send + Sync> Graph<Q, R> {
+    /// Finds every query resolved in this iteration so far that isn't volatile
+    /// but transitively depends on a volatile query, with the shortest chain
+    /// of dependencies to one of them.
+    ///
+    /// This catches impure reads that were added under cached queries. Only
+    /// the dependencies of queries resolved so far are known, so it's best
+    /// called after the queries of interest were resolved.
+    pub fn purity_violations(&self) -> Vec<PurityViolation<Q>> {
+        let dependents = self.reverse_edges();
+
+        // A BFS over the reverse edges from every volatile query, remembering
+        // which dependency each query was reached from.
+        let mut reached_from = HashMap::<Q, Option<Q>>::new();
+        let mut queue = VecDeque::new();
+
+        for (q, node) in self.new.iter() {
+            if node.get().is_some() && self.resolver.is_volatile(&q) {
+                reached_from.insert(q.clone(), None);
+                queue.push_back(q);
+            }
+        }
+
+        let mut violations = Vec::new();
+
+        while let Some(q) = queue.pop_front() {
+            for dependent in dependents.get(&q).into_iter().flatten() {
+                if reached_from.contains_key(dependent) {
+                    continue;
+                }
+
+                reached_from.insert(dependent.clone(), Some(q.clone()));
+                queue.push_back(dependent.clone());
+
+                let mut chain = vec![dependent.clone()];
+
+                while let Some(Some(next)) = chain.last().map(|q| &reached_from[q]) {
+                    chain.push(next.clone());
+                }
+
+                violations.push(PurityViolation {
+                    query: dependent.clone(),
+                    volatile: chain.last().unwrap().clone(),
+                    chain,
+                });
+            }
+        }
+
+        violations
+    }
+}
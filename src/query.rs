@@ -0,0 +1,54 @@
+use std::{any::type_name, hash::Hash, sync::Arc};
+
+use crate::{Graph, QueryResolver};
+
+/// A `Query` is a typed query key: it declares the type of its result, so it
+/// can be queried without matching on the graph's result type by hand.
+///
+/// A graph still has a single query type `Q` and result type `R`, so typed
+/// queries are converted into `Q` (with `Into`), and their results out of `R`
+/// (with `TryInto`), which is exactly what the typical enum of queries and
+/// enum of results implement (with one variant per query).
+///
+/// With the `derive` feature, `#[derive(Query)]` implements this (along with
+/// `Clone`, `Eq`, and `Hash`) for structs, with the output type declared with
+/// `#[query(output = T)]`.
+pub trait Query: Clone + Eq + Hash + Send + Sync {
+    type Output;
+}
+
+impl<Q: Clone + Eq + Hash + Send + Sync, R: Clone + Send + Sync> Graph<Q, R> {
+    /// Queries the typed query, converting it into `Q` and the result into
+    /// its output type. Panics if the result doesn't convert, which means the
+    /// resolver returned the wrong kind of result for the query.
+    pub fn query_typed<T>(self: &Arc<Self>, q: T) -> T::Output
+    where
+        T: Query + Into<Q>,
+        R: TryInto<T::Output>,
+    {
+        convert::<T, R>(self.query(q.into()))
+    }
+}
+
+impl<Q: Clone + Eq + Hash + Send + Sync, R: Clone + Send + Sync> QueryResolver<Q, R> {
+    /// Like `Graph::query_typed`, but records the dependency like `query`.
+    pub fn query_typed<T>(&self, q: T) -> T::Output
+    where
+        T: Query + Into<Q>,
+        R: TryInto<T::Output>,
+    {
+        convert::<T, R>(self.query(q.into()))
+    }
+}
+
+#[track_caller]
+fn convert<T: Query, R: TryInto<T::Output>>(result: R) -> T::Output {
+    match result.try_into() {
+        Ok(output) => output,
+        Err(_) => panic!(
+            "the result of `{}` isn't a `{}`",
+            type_name::<T>(),
+            type_name::<T::Output>()
+        ),
+    }
+}
@@ -0,0 +1,169 @@
+use std::{fmt::Debug, hash::Hash};
+
+use crate::{compress::Stored, store::NodeMap, Graph, NodeRef, Resolution};
+
+type Redact<T> = Box<dyn Fn(&T) -> Option<String> + Send + Sync>;
+
+/// How queries and results are redacted in the `Debug` output the graph
+/// produces (see `GraphBuilder::redact_queries`).
+pub(crate) struct Redaction<Q, R> {
+    pub(crate) query: Option<Redact<Q>>,
+    pub(crate) result: Option<Redact<R>>,
+}
+
+impl<Q, R> Default for Redaction<Q, R> {
+    fn default() -> Self {
+        Self {
+            query: None,
+            result: None,
+        }
+    }
+}
+
+impl<Q, R> Graph<Q, R> {
+    /// Describes the query with `debug` (its `Debug` output), unless it's
+    /// redacted.
+    pub(crate) fn describe_query(&self, q: &Q, debug: impl FnOnce(&Q) -> String) -> String {
+        let redacted = self
+            .config
+            .redaction
+            .query
+            .as_ref()
+            .and_then(|redact| redact(q));
+        redacted.unwrap_or_else(|| debug(q))
+    }
+
+    /// Describes the result with `debug` (its `Debug` output), unless it's
+    /// redacted.
+    pub(crate) fn describe_result(&self, result: &R, debug: impl FnOnce(&R) -> String) -> String {
+        let redact = self.config.redaction.result.as_ref();
+        let redacted = redact.and_then(|redact| redact(result));
+        redacted.unwrap_or_else(|| debug(result))
+    }
+
+    /// Formats the nodes of the map for the `Debug` impl of the graph.
+    pub(crate) fn debug_nodes<'g>(
+        &'g self,
+        nodes: &'g NodeMap<Q, NodeRef<Q, R>>,
+    ) -> DebugNodes<'g, Q, R> {
+        DebugNodes { graph: self, nodes }
+    }
+}
+
+/// The nodes of a map, with their queries, results and edges described (and so
+/// redacted) like in any other output of the graph. Compressed results aren't
+/// decompressed just to be formatted.
+pub(crate) struct DebugNodes<'g, Q, R> {
+    graph: &'g Graph<Q, R>,
+    nodes: &'g NodeMap<Q, NodeRef<Q, R>>,
+}
+
+/// A query or result that was already described, shown as is.
+struct Described(String);
+
+impl Debug for Described {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+struct DescribedNode {
+    result: Described,
+    changed: bool,
+    edges_from: Vec<Described>,
+    resolution: Resolution,
+}
+
+impl Debug for DescribedNode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Node")
+            .field("result", &self.result)
+            .field("changed", &self.changed)
+            .field("edges_from", &self.edges_from)
+            .field("resolution", &self.resolution)
+            .finish()
+    }
+}
+
+impl<Q: Debug + Clone + Eq + Hash, R: Debug> Debug for DebugNodes<'_, Q, R> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let describe = |q: &Q| Described(self.graph.describe_query(q, |q| format!("{:?}", q)));
+        let mut map = f.debug_map();
+
+        for (q, slot) in self.nodes.iter() {
+            let Some(node) = slot.get() else {
+                map.entry(&describe(&q), &Described("<unresolved>".to_owned()));
+                continue;
+            };
+
+            let result = match &node.result {
+                Stored::Value(result) => self
+                    .graph
+                    .describe_result(result, |result| format!("{:?}", result)),
+                Stored::Compressed(_) => "<compressed>".to_owned(),
+            };
+
+            map.entry(
+                &describe(&q),
+                &DescribedNode {
+                    result: Described(result),
+                    changed: node.changed,
+                    edges_from: node.edges_from.iter().map(describe).collect(),
+                    resolution: node.resolution,
+                },
+            );
+        }
+
+        map.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::{GraphBuilder, QueryResolver, ResolveQuery};
+
+    /// Reads a "file", whose path and contents are secret, through a query
+    /// that depends on it.
+    struct SecretResolver;
+
+    impl ResolveQuery<String, String> for SecretResolver {
+        fn resolve(&self, q: String, resolve: Arc<QueryResolver<String, String>>) -> String {
+            match q.strip_prefix("len:") {
+                Some(path) => resolve.query(path.to_owned()).len().to_string(),
+                None => format!("contents of {}", q),
+            }
+        }
+    }
+
+    #[test]
+    fn debug_output_is_redacted() {
+        let graph = GraphBuilder::new()
+            .redact_queries(|q: &String| {
+                q.contains("secret")
+                    .then(|| q.replace("secret.txt", "<path>"))
+            })
+            .redact_results(|result: &String| {
+                result.contains("secret").then(|| "<contents>".to_owned())
+            })
+            .build(SecretResolver);
+
+        assert_eq!(graph.query("len:secret.txt".to_owned()), "22");
+
+        let debug = format!("{:?}", graph);
+
+        assert!(!debug.contains("secret"), "{}", debug);
+        assert!(
+            debug.contains("<path>: Node { result: <contents>"),
+            "{}",
+            debug
+        );
+        assert!(
+            debug.contains("len:<path>: Node { result: \"22\""),
+            "{}",
+            debug
+        );
+        assert!(debug.contains("edges_from: [<path>]"), "{}", debug);
+    }
+}
@@ -0,0 +1,202 @@
+use std::{hash::Hash, sync::atomic::Ordering, thread};
+
+use hashbrown::HashSet;
+
+use crate::{Graph, Node, NodeRef, Slot};
+
+/// The progress of validating a node against its old node when the validation
+/// was interrupted (by cancellation). It's kept in the node's slot, so that the
+/// next iteration can resume it instead of resolving the query from scratch.
+#[derive(Debug)]
+pub(crate) struct PartialValidation<Q, R> {
+    /// The slot of the old node being validated against.
+    pub(crate) old: NodeRef<Q, R>,
+    /// The dependencies confirmed to be unchanged so far.
+    pub(crate) confirmed: HashSet<Q>,
+}
+
+impl<Q: Clone + Eq + Hash + Send + Sync, R: Send + Sync> Graph<Q, R> {
+    /// Takes the progress of the validation the previous iteration was doing
+    /// when it was interrupted, if the old slot was left unresolved by one.
+    pub(crate) fn interrupted_validation(
+        &self,
+        old: &Slot<Q, R>,
+    ) -> Option<PartialValidation<Q, R>> {
+        // A slot that's still being resolved wasn't interrupted (yet).
+        if old.get().is_some() || old.resolving.load(Ordering::Acquire) {
+            return None;
+        }
+
+        old.partial.lock().take()
+    }
+}
+
+/// Guards the validation of a slot's node against its old node. If the
+/// validation is interrupted by cancellation (it unwinds while the iteration
+/// is cancelled), the guard records which dependencies were confirmed
+/// unchanged, so that the next iteration can resume it. Validations that
+/// aren't interrupted don't record anything.
+pub(crate) struct Interruption<'a, Q: Clone + Eq + Hash + Send + Sync, R: Send + Sync> {
+    pub(crate) graph: &'a Graph<Q, R>,
+    pub(crate) slot: &'a Slot<Q, R>,
+    pub(crate) old: &'a NodeRef<Q, R>,
+    pub(crate) old_node: &'a Node<Q, R>,
+    /// The interrupted validation this one resumes, if any.
+    pub(crate) resumed: Option<&'a PartialValidation<Q, R>>,
+}
+
+impl<Q: Clone + Eq + Hash + Send + Sync, R: Send + Sync> Drop for Interruption<'_, Q, R> {
+    fn drop(&mut self) {
+        if !thread::panicking() || !self.graph.is_cancelled() {
+            return;
+        }
+
+        // The dependencies resolved (and unchanged) in this iteration are the
+        // ones the validation confirmed, or would have. When resuming, only
+        // the ones the resumed validation confirmed count, since the others
+        // may have changed since the node's revision.
+        let confirmed = self
+            .old_node
+            .edges_from
+            .iter()
+            .filter(|parent| {
+                self.resumed
+                    .is_none_or(|partial| partial.confirmed.contains(*parent))
+            })
+            .filter(|parent| {
+                self.graph.new.get(parent).is_some_and(|slot| {
+                    slot.get().is_some_and(|node| {
+                        !self.graph.dependency_changed(self.old_node, parent, node)
+                    })
+                })
+            })
+            .cloned()
+            .collect();
+
+        *self.slot.partial.lock() = Some(PartialValidation {
+            old: self.old.clone(),
+            confirmed,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{panic::AssertUnwindSafe, sync::Arc};
+
+    use parking_lot::Mutex;
+
+    use crate::{Cancelled, Graph, GraphBuilder, QueryResolver, Resolution, ResolveQuery};
+
+    #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+    enum Query {
+        Double,
+        Sum,
+        Input,
+        Other,
+    }
+
+    /// `Sum` adds the inputs, and resolving `Input` cancels the iteration
+    /// while `cancel` is set.
+    #[derive(Clone, Default)]
+    struct Resolver {
+        cancel: Arc<Mutex<bool>>,
+    }
+
+    impl ResolveQuery<Query, u32> for Resolver {
+        fn resolve(&self, q: Query, resolve: Arc<QueryResolver<Query, u32>>) -> u32 {
+            match q {
+                Query::Double => resolve.query(Query::Input) * 2,
+                Query::Sum => resolve.query(Query::Other) + resolve.query(Query::Input),
+                Query::Other => 1,
+                Query::Input => {
+                    if *self.cancel.lock() {
+                        resolve.graph.cancel();
+                        resolve.check_cancelled();
+                    }
+
+                    1
+                }
+            }
+        }
+
+        fn is_input(&self, q: &Query) -> bool {
+            matches!(q, Query::Input | Query::Other)
+        }
+    }
+
+    #[test]
+    fn interrupted_validations_are_resumed() {
+        let resolver = Resolver::default();
+        let resolutions = Arc::new(Mutex::new(Vec::new()));
+
+        let graph = GraphBuilder::new()
+            .on_resolve({
+                let resolutions = resolutions.clone();
+                move |q: &Query, resolution| resolutions.lock().push((q.clone(), resolution))
+            })
+            .build(resolver.clone());
+
+        assert_eq!(graph.query(Query::Double), 2);
+
+        // Validating `Double` is interrupted while the input is resolved.
+        graph.invalidate(Query::Input);
+        *resolver.cancel.lock() = true;
+
+        let graph = graph.increment(resolver.clone());
+        let cancelled = Cancelled::catch(AssertUnwindSafe(|| graph.query(Query::Double)));
+        assert_eq!(cancelled, Err(Cancelled));
+
+        *resolver.cancel.lock() = false;
+        resolutions.lock().clear();
+
+        // The next iteration validates `Double` against the node the interrupted
+        // one was validating (instead of resolving it from scratch), so its
+        // result is compared against it and found unchanged. The input has no
+        // validation to resume, and its old node was never resolved.
+        let graph = graph.cancel_and_increment(resolver);
+
+        assert_eq!(graph.query(Query::Double), 2);
+        assert_eq!(
+            *resolutions.lock(),
+            [
+                (Query::Input, Resolution::Fresh),
+                (Query::Double, Resolution::Recomputed),
+            ]
+        );
+
+        let slot = graph.new.get(&Query::Double).unwrap();
+        assert!(!slot.get().unwrap().changed);
+        assert!(slot.partial.lock().is_none());
+    }
+
+    #[test]
+    fn only_interrupted_validations_are_recorded() {
+        let resolver = Resolver::default();
+        let graph = Graph::new(resolver.clone());
+
+        assert_eq!(graph.query(Query::Sum), 2);
+
+        let graph = graph.increment(resolver.clone());
+        assert_eq!(graph.query(Query::Sum), 2);
+
+        let slot = graph.new.get(&Query::Sum).unwrap();
+        assert!(slot.partial.lock().is_none());
+
+        // The validation is interrupted after `Other` was confirmed unchanged.
+        graph.invalidate(Query::Input);
+        *resolver.cancel.lock() = true;
+
+        let graph = graph.increment(resolver);
+        assert_eq!(graph.query(Query::Other), 1);
+
+        let cancelled = Cancelled::catch(AssertUnwindSafe(|| graph.query(Query::Sum)));
+        assert_eq!(cancelled, Err(Cancelled));
+
+        let slot = graph.new.get(&Query::Sum).unwrap();
+        let partial = slot.partial.lock();
+        let confirmed = partial.as_ref().map(|partial| partial.confirmed.clone());
+
+        assert_eq!(confirmed, Some([Query::Other].into_iter().collect()));
+    }
+}
@@ -0,0 +1,35 @@
+use std::{
+    hash::Hash,
+    time::{Duration, Instant},
+};
+
+use crate::Graph;
+
+/// Reports results that took longer than the threshold to clone (see
+/// `GraphBuilder::detect_slow_clones`).
+pub(crate) struct SlowClones<Q> {
+    pub(crate) threshold: Duration,
+    pub(crate) on_slow_clone: SlowCloneCallback<Q>,
+}
+
+type SlowCloneCallback<Q> = Box<dyn Fn(&Q, Duration) + Send + Sync>;
+
+impl<Q: Clone + Eq + Hash + Send + Sync, R: Clone + Send + Sync> Graph<Q, R> {
+    /// Clones the result of the query, timing the clone if slow clones are
+    /// detected.
+    pub(crate) fn clone_result(&self, q: &Q, result: &R) -> R {
+        let Some(slow_clones) = &self.config.slow_clones else {
+            return result.clone();
+        };
+
+        let start = Instant::now();
+        let result = result.clone();
+        let elapsed = start.elapsed();
+
+        if elapsed > slow_clones.threshold {
+            (slow_clones.on_slow_clone)(q, elapsed);
+        }
+
+        result
+    }
+}
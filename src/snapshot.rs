@@ -0,0 +1,133 @@
+use std::{hash::Hash, sync::Arc};
+
+use crate::{stats::Resolution, Graph, Node, Slot};
+
+/// A `Snapshot` is a copy of every resolved node in an iteration (its query,
+/// result, and dependencies), which can be used to restore the graph later
+/// (see `GraphBuilder::build_from_snapshot`). With the `serde` feature enabled
+/// it can be serialized, which makes it easy to persist the cache to disk.
+///
+/// The nodes of a snapshot are in whatever order they were stored in, which
+/// depends on the hasher's random state, so serializing the same graph twice
+/// doesn't produce the same bytes. Use [`Snapshot::sorted`] when the output
+/// has to be reproducible (e.g. for content-addressed caches). Files written
+/// with `Graph::snapshot_to_file` are always reproducible, even if `Q` isn't
+/// `Ord`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Snapshot<Q, R> {
+    nodes: Vec<SnapshotNode<Q, R>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+struct SnapshotNode<Q, R> {
+    query: Q,
+    result: R,
+    edges_from: Vec<Q>,
+}
+
+impl<Q, R> Snapshot<Q, R> {
+    /// The number of nodes in the snapshot.
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// Sorts the nodes (and the dependencies of each node) by query, so that
+    /// the same logical graph always produces the same snapshot, no matter the
+    /// order it was resolved or stored in.
+    ///
+    /// This sorts by `Q: Ord` rather than hashing with a fixed seed, since a
+    /// sort stays deterministic across hash collisions and hasher versions.
+    pub fn sorted(mut self) -> Self
+    where
+        Q: Ord,
+    {
+        for node in &mut self.nodes {
+            node.edges_from.sort_unstable();
+        }
+
+        self.nodes.sort_unstable_by(|a, b| a.query.cmp(&b.query));
+        self
+    }
+
+    /// Like `sorted`, but sorts by a key of each query (computed once per
+    /// query), for queries that aren't `Ord`.
+    #[cfg(feature = "bincode")]
+    pub(crate) fn sorted_by_cached_key<K: Ord>(mut self, mut key: impl FnMut(&Q) -> K) -> Self {
+        for node in &mut self.nodes {
+            node.edges_from.sort_by_cached_key(&mut key);
+        }
+
+        self.nodes.sort_by_cached_key(|node| key(&node.query));
+        self
+    }
+}
+
+/// Collects a snapshot of input nodes (nodes without dependencies), like the
+/// initial inputs of a graph (see `Graph::from_inputs`).
+impl<Q, R> FromIterator<(Q, R)> for Snapshot<Q, R> {
+    fn from_iter<I: IntoIterator<Item = (Q, R)>>(iter: I) -> Self {
+        let nodes = iter
+            .into_iter()
+            .map(|(query, result)| SnapshotNode {
+                query,
+                result,
+                edges_from: Vec::new(),
+            })
+            .collect();
+
+        Self { nodes }
+    }
+}
+
+impl<Q: Clone + Eq + Hash + Send + Sync, R: Clone + Send + Sync> Graph<Q, R> {
+    /// Takes a snapshot of every node resolved in this iteration so far.
+    pub fn snapshot(&self) -> Snapshot<Q, R> {
+        let nodes = self
+            .new
+            .iter()
+            .filter_map(|(query, node)| {
+                let node = node.get()?;
+
+                Some(SnapshotNode {
+                    query,
+                    result: self.load(&node.result).as_ref().clone(),
+                    edges_from: node.edges_from.iter().cloned().collect(),
+                })
+            })
+            .collect();
+
+        Snapshot { nodes }
+    }
+}
+
+impl<Q: Clone + Eq + Hash + Send + Sync, R: Send + Sync> Graph<Q, R> {
+    /// Seeds the old map with the nodes of the snapshot, so that they're
+    /// validated (instead of resolved from scratch) in this iteration.
+    pub(crate) fn restore(&self, snapshot: Snapshot<Q, R>) {
+        self.old.extend(snapshot.nodes.into_iter().map(|node| {
+            let SnapshotNode {
+                query,
+                result,
+                edges_from,
+            } = node;
+
+            let node = Node {
+                result: self.store(result),
+                changed: false,
+                edges_from: Arc::new(edges_from.into_iter().collect()),
+                external_edges: Arc::new([]),
+                parts: Default::default(),
+                resolution: Resolution::Fresh,
+                resolutions: 0,
+            };
+
+            (query, Arc::new(Slot::resolved(node)))
+        }));
+    }
+}
@@ -0,0 +1,63 @@
+use std::{hash::Hash, sync::Arc};
+
+use crate::{Graph, ResolveQuery};
+
+/// An iteration built in a staging area, which only becomes the latest
+/// iteration of its graph once it's committed (see `Graph::begin_increment`).
+pub struct StagedIncrement<Q, R> {
+    graph: Arc<Graph<Q, R>>,
+}
+
+impl<Q: Clone + Eq + Hash + Send + Sync, R: Send + Sync> StagedIncrement<Q, R> {
+    /// The staged iteration, to resolve (e.g. with `resolve_to_completion`)
+    /// before committing it.
+    pub fn graph(&self) -> &Arc<Graph<Q, R>> {
+        &self.graph
+    }
+
+    /// Atomically makes the staged iteration the latest one, so that readers
+    /// (see `Graph::latest`) and the other graphs depending on this one see it
+    /// from now on, and returns it. If several increments are staged at once,
+    /// the last one committed wins.
+    pub fn commit(self) -> Arc<Graph<Q, R>> {
+        self.graph.become_latest();
+        self.graph
+    }
+}
+
+impl<Q: Clone + Eq + Hash + Send + Sync, R: Send + Sync> Graph<Q, R> {
+    /// Like `increment`, but the new iteration is staged: it isn't the latest
+    /// iteration (as far as `latest` and the other graphs depending on this one
+    /// are concerned) until it's committed. Readers that always query the
+    /// latest iteration never see a half built one, they keep querying this
+    /// one until the staged iteration is resolved and committed. Dropping the
+    /// staged increment instead discards it.
+    ///
+    /// This gives snapshot isolation, but it's heavier than the default eager
+    /// model, where an increment is the latest iteration right away and its
+    /// queries are resolved lazily, as readers make them:
+    ///
+    /// - Everything readers will need has to be resolved up front, before
+    ///   committing, so nothing is gained from laziness.
+    /// - Both iterations (and their nodes) are kept alive until the commit.
+    /// - Readers keep getting results of the previous iteration while the
+    ///   staged one is being resolved, so they can be arbitrarily stale.
+    pub fn begin_increment(
+        self: &Arc<Self>,
+        resolver: impl ResolveQuery<Q, R> + 'static,
+    ) -> StagedIncrement<Q, R> {
+        StagedIncrement {
+            graph: self.stage_iteration(Arc::new(resolver)),
+        }
+    }
+
+    /// Returns the latest iteration of this graph (the one most recently
+    /// incremented from it, or committed), or this iteration if it's the latest
+    /// one (or every newer one was dropped).
+    pub fn latest(self: &Arc<Self>) -> Arc<Self> {
+        self.lineage
+            .lock()
+            .upgrade()
+            .unwrap_or_else(|| self.clone())
+    }
+}
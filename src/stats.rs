@@ -0,0 +1,398 @@
+use std::{
+    cmp::Reverse,
+    hash::Hash,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use hashbrown::HashMap;
+
+use crate::Graph;
+
+/// How a node was resolved in its iteration (see `GraphBuilder::on_resolve`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resolution {
+    /// Resolved from scratch, without an old result to validate.
+    Fresh,
+    /// Validated against the old result and resolved again.
+    Recomputed,
+    /// Validated against the old result, which was reused.
+    Reused,
+}
+
+/// Statistics about how the queries of an iteration were resolved.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Stats {
+    /// Queries resolved from scratch (new queries, or queries whose old node
+    /// wasn't resolved in the previous iteration).
+    pub fresh: usize,
+    /// Queries that were revalidated and had to be resolved again.
+    pub recomputed: usize,
+    /// Queries that were revalidated and reused their old result.
+    pub reused: usize,
+}
+
+impl Stats {
+    /// The total number of queries resolved in the iteration.
+    pub fn resolved(&self) -> usize {
+        self.fresh + self.recomputed + self.reused
+    }
+}
+
+/// Statistics about the shape of the dependency graph of an iteration (see
+/// `Graph::topology_stats`).
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct TopologyStats {
+    /// The number of resolved nodes.
+    pub nodes: usize,
+    /// The most dependencies of a single node.
+    pub max_fan_out: usize,
+    /// The average number of dependencies of a node.
+    pub avg_fan_out: f64,
+    /// The most dependents of a single node.
+    pub max_fan_in: usize,
+    /// The average number of dependents of a node.
+    pub avg_fan_in: f64,
+}
+
+/// A query with so many dependents that it's likely a poor incrementality
+/// boundary (see `Graph::incrementality_warnings`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IncrementalityWarning<Q> {
+    /// The query with many dependents (like a `GetAllDocuments` query, whose
+    /// result changes whenever any document is added or removed).
+    pub hub: Q,
+    /// The queries that depend on the hub, which all have to be validated
+    /// (and are likely resolved again) whenever its result changes.
+    pub dependents: Vec<Q>,
+}
+
+/// What incrementing a graph would entail (see `Graph::plan_increment`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IncrementPlan<Q> {
+    /// The queries that would have to be resolved again (the roots whose
+    /// result changed, and everything depending on them).
+    pub to_recompute: Vec<Q>,
+    /// The estimated number of calls to the resolver it would take to bring
+    /// the queries of the current iteration up to date (including the roots,
+    /// which are always resolved again unless they're inputs).
+    pub estimated_cost: usize,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct StatsCounters {
+    fresh: AtomicUsize,
+    recomputed: AtomicUsize,
+    reused: AtomicUsize,
+}
+
+impl StatsCounters {
+    pub(crate) fn record(&self, resolution: Resolution) {
+        let counter = match resolution {
+            Resolution::Fresh => &self.fresh,
+            Resolution::Recomputed => &self.recomputed,
+            Resolution::Reused => &self.reused,
+        };
+
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn snapshot(&self) -> Stats {
+        Stats {
+            fresh: self.fresh.load(Ordering::Relaxed),
+            recomputed: self.recomputed.load(Ordering::Relaxed),
+            reused: self.reused.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl<Q: Clone + Eq + Hash + Send + Sync, R: Send + Sync> Graph<Q, R> {
+    /// Returns the statistics of this iteration so far.
+    pub fn stats(&self) -> Stats {
+        self.stats.snapshot()
+    }
+
+    /// Returns the fraction (between 0 and 1) of the queries resolved in this
+    /// iteration so far that reused their old result, which is how much work
+    /// incrementality saved. It's 0 if nothing was resolved yet.
+    pub fn estimated_incremental_savings(&self) -> f64 {
+        let stats = self.stats();
+
+        match stats.resolved() {
+            0 => 0.0,
+            resolved => stats.reused as f64 / resolved as f64,
+        }
+    }
+
+    /// Returns the `n` queries (resolved in this iteration so far) that the
+    /// resolver resolved the most times across every iteration, along with how
+    /// many times, most resolved first. Queries that keep being resolved again
+    /// aren't benefiting from incrementality, which usually means they have a
+    /// too coarse dependency or depend on an impure input.
+    pub fn hot_queries(&self, n: usize) -> Vec<(Q, u64)> {
+        let mut hot = self
+            .new
+            .iter()
+            .filter_map(|(q, slot)| slot.get().map(|node| (q, node.resolutions)))
+            .collect::<Vec<_>>();
+
+        hot.sort_unstable_by(|(_, a), (_, b)| b.cmp(a));
+        hot.truncate(n);
+        hot
+    }
+
+    /// Returns the fan-out (dependencies) and fan-in (dependents) statistics of
+    /// the nodes resolved in this iteration so far. Nodes with a high fan-in
+    /// are bottlenecks for incrementality, since a change to them has to be
+    /// validated by every dependent.
+    pub fn topology_stats(&self) -> TopologyStats {
+        let dependents = self.reverse_edges();
+
+        let mut stats = TopologyStats::default();
+        let mut fan_out = 0;
+        let mut fan_in = 0;
+
+        for (q, slot) in self.new.iter() {
+            let Some(node) = slot.get() else {
+                continue;
+            };
+
+            let node_fan_in = dependents.get(&q).map_or(0, Vec::len);
+
+            stats.nodes += 1;
+            stats.max_fan_out = stats.max_fan_out.max(node.edges_from.len());
+            stats.max_fan_in = stats.max_fan_in.max(node_fan_in);
+            fan_out += node.edges_from.len();
+            fan_in += node_fan_in;
+        }
+
+        if stats.nodes > 0 {
+            stats.avg_fan_out = fan_out as f64 / stats.nodes as f64;
+            stats.avg_fan_in = fan_in as f64 / stats.nodes as f64;
+        }
+
+        stats
+    }
+
+    /// Flags the "hub" queries resolved in this iteration so far, those whose
+    /// fan-in is far above average (by default at least 4 times the average,
+    /// and at least 8, see `GraphBuilder::hub_thresholds`), along with the
+    /// queries that depend on them, most dependents first.
+    ///
+    /// A query depending on a hub is invalidated whenever the hub changes,
+    /// which for aggregate queries (like a list of every document) is after
+    /// almost every edit. This is a common reason for a low cache hit rate,
+    /// usually fixed by depending on a narrower query (like a single document)
+    /// instead.
+    pub fn incrementality_warnings(&self) -> Vec<IncrementalityWarning<Q>> {
+        let (factor, min_fan_in) = self.config.hub_thresholds;
+
+        let threshold = (self.topology_stats().avg_fan_in * factor).ceil() as usize;
+        let threshold = threshold.max(min_fan_in);
+
+        let mut warnings = self
+            .reverse_edges()
+            .iter()
+            .filter(|(_, dependents)| dependents.len() >= threshold)
+            .map(|(hub, dependents)| IncrementalityWarning {
+                hub: hub.clone(),
+                dependents: dependents.clone(),
+            })
+            .collect::<Vec<_>>();
+
+        warnings.sort_unstable_by_key(|warning| Reverse(warning.dependents.len()));
+        warnings
+    }
+
+    /// Returns the length of the longest chain of dependencies from the query
+    /// down to a root (so a root has a depth of 0), or `None` if the query
+    /// wasn't resolved in this iteration. Deep chains have to be resolved one
+    /// query after the other on a cold build, so they limit parallelism.
+    pub fn depth_of(&self, q: &Q) -> Option<usize> {
+        self.new.get(q)?.get()?;
+
+        // The depths are memoized, and computed with an explicit stack (in
+        // post-order) so that deep chains can't overflow the call stack.
+        let mut depths = HashMap::<Q, usize>::new();
+        let mut stack = vec![(q.clone(), false)];
+
+        while let Some((q, expanded)) = stack.pop() {
+            if depths.contains_key(&q) {
+                continue;
+            }
+
+            let edges = self
+                .new
+                .get(&q)
+                .and_then(|slot| slot.get().map(|node| node.edges_from.clone()));
+
+            let Some(edges) = edges else {
+                depths.insert(q, 0);
+                continue;
+            };
+
+            if expanded {
+                let depth = edges
+                    .iter()
+                    .map(|parent| depths.get(parent).map_or(0, |depth| depth + 1))
+                    .max()
+                    .unwrap_or(0);
+
+                depths.insert(q, depth);
+            } else {
+                let unvisited = edges
+                    .iter()
+                    .filter(|parent| !depths.contains_key(*parent))
+                    .map(|parent| (parent.clone(), false))
+                    .collect::<Vec<_>>();
+
+                stack.push((q, true));
+                stack.extend(unvisited);
+            }
+        }
+
+        depths.get(q).copied()
+    }
+
+    /// Formats the statistics of this iteration, the number of nodes, and a
+    /// (rough) estimate of their memory usage in the Prometheus text format,
+    /// ready to be scraped. The counters start from zero with every increment,
+    /// which Prometheus handles like any other counter reset.
+    #[cfg(feature = "metrics")]
+    pub fn prometheus_metrics(&self) -> String {
+        use std::fmt::Write;
+
+        let stats = self.stats();
+        let new_nodes = self.new.len();
+        let old_nodes = self.old.len();
+        let memory = (new_nodes + old_nodes) * self.estimated_node_size();
+
+        let mut metrics = String::new();
+
+        writeln!(
+            metrics,
+            "# HELP query_graph_resolved_total Queries resolved in the current iteration."
+        )
+        .unwrap();
+        writeln!(metrics, "# TYPE query_graph_resolved_total counter").unwrap();
+
+        for (resolution, count) in [
+            ("fresh", stats.fresh),
+            ("recomputed", stats.recomputed),
+            ("reused", stats.reused),
+        ] {
+            writeln!(
+                metrics,
+                "query_graph_resolved_total{{resolution=\"{}\"}} {}",
+                resolution, count
+            )
+            .unwrap();
+        }
+
+        writeln!(
+            metrics,
+            "# HELP query_graph_nodes Nodes stored in the current and previous iteration."
+        )
+        .unwrap();
+        writeln!(metrics, "# TYPE query_graph_nodes gauge").unwrap();
+        writeln!(metrics, "query_graph_nodes{{map=\"new\"}} {}", new_nodes).unwrap();
+        writeln!(metrics, "query_graph_nodes{{map=\"old\"}} {}", old_nodes).unwrap();
+
+        writeln!(
+            metrics,
+            "# HELP query_graph_memory_bytes Estimated memory used by the stored nodes."
+        )
+        .unwrap();
+        writeln!(metrics, "# TYPE query_graph_memory_bytes gauge").unwrap();
+        writeln!(metrics, "query_graph_memory_bytes {}", memory).unwrap();
+
+        metrics
+    }
+
+    /// A rough estimate of the memory used by a single node, which ignores any
+    /// memory owned by the query and result themselves (and the edges).
+    #[cfg(feature = "metrics")]
+    fn estimated_node_size(&self) -> usize {
+        use std::mem::size_of;
+
+        size_of::<Q>() + size_of::<crate::Node<Q, R>>() + size_of::<R>()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::{GraphBuilder, QueryResolver, ResolveQuery};
+
+    #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+    enum Star {
+        Root,
+        Spoke(u32),
+        Hub,
+    }
+
+    /// A star: every spoke depends on the hub, and the root on every spoke.
+    struct StarResolver {
+        spokes: u32,
+    }
+
+    impl ResolveQuery<Star, u32> for StarResolver {
+        fn resolve(&self, q: Star, resolve: Arc<QueryResolver<Star, u32>>) -> u32 {
+            match q {
+                Star::Root => (0..self.spokes)
+                    .map(|i| resolve.query(Star::Spoke(i)))
+                    .sum(),
+                Star::Spoke(i) => resolve.query(Star::Hub) + i,
+                Star::Hub => 1,
+            }
+        }
+    }
+
+    #[test]
+    fn the_hub_of_a_star_is_flagged() {
+        let graph = GraphBuilder::new().build(StarResolver { spokes: 20 });
+        graph.query(Star::Root);
+
+        let warnings = graph.incrementality_warnings();
+
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].hub, Star::Hub);
+
+        let mut dependents = warnings[0].dependents.clone();
+        dependents.sort_unstable_by_key(|q| match q {
+            Star::Spoke(i) => *i,
+            _ => unreachable!(),
+        });
+
+        assert_eq!(dependents, (0..20).map(Star::Spoke).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn small_stars_are_not_flagged() {
+        let graph = GraphBuilder::new().build(StarResolver { spokes: 5 });
+        graph.query(Star::Root);
+
+        assert!(graph.incrementality_warnings().is_empty());
+    }
+
+    #[test]
+    fn hub_thresholds_are_configurable() {
+        let graph = GraphBuilder::new()
+            .hub_thresholds(2.0, 3)
+            .build(StarResolver { spokes: 5 });
+        graph.query(Star::Root);
+
+        // The hub's fan-in (5) is about 3.5 times the average (10 / 7).
+        let warnings = graph.incrementality_warnings();
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].hub, Star::Hub);
+
+        // The hub's fan-in (20) is about 11 times the average (40 / 22).
+        let graph = GraphBuilder::new()
+            .hub_thresholds(12.0, 1)
+            .build(StarResolver { spokes: 20 });
+        graph.query(Star::Root);
+
+        assert!(graph.incrementality_warnings().is_empty());
+    }
+}
@@ -0,0 +1,109 @@
+use std::{fmt::Debug, hash::Hash, sync::OnceLock};
+
+use rayon::{
+    iter::Either,
+    prelude::{IntoParallelIterator, ParallelIterator},
+};
+
+use crate::map::{ConcurrentMap, NodeStore};
+
+/// The map a graph stores its nodes in. The default `ConcurrentMap` is used
+/// directly, so only custom stores pay for dynamic dispatch.
+///
+/// A graph with a single query (see `Graph::singleton`) keeps its only entry in
+/// a once-lock instead, so getting it takes neither hashing nor locking. The
+/// entry is never dropped (like a pinned query), and it panics if a second,
+/// different key is inserted.
+pub(crate) enum NodeMap<K, V> {
+    Concurrent(ConcurrentMap<K, V>),
+    Custom(Box<dyn NodeStore<K, V>>),
+    Singleton(OnceLock<(K, V)>),
+}
+
+impl<K: Clone + Eq + Hash, V: Clone> NodeMap<K, V> {
+    pub(crate) fn get(&self, key: &K) -> Option<V> {
+        match self {
+            Self::Concurrent(map) => map.get(key),
+            Self::Custom(store) => store.get(key),
+            Self::Singleton(entry) => match entry.get() {
+                Some((k, value)) if k == key => Some(value.clone()),
+                _ => None,
+            },
+        }
+    }
+
+    /// Gets the value of the key, inserting `value(key)` first if there isn't
+    /// one. The key is only cloned if it's inserted.
+    pub(crate) fn get_or_insert_with_key<F: FnOnce(&K) -> V>(&self, key: &K, value: F) -> V {
+        match self {
+            Self::Concurrent(map) => map.get_or_insert_with_key(key, value),
+            Self::Custom(store) => store.get(key).unwrap_or_else(|| {
+                let mut value = Some(value);
+                store.get_or_insert(key.clone(), &mut || (value.take().unwrap())(key))
+            }),
+            Self::Singleton(entry) => {
+                let (k, value) = entry.get_or_init(|| (key.clone(), value(key)));
+                assert!(k == key, "a singleton graph can only hold a single query");
+                value.clone()
+            }
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        match self {
+            Self::Concurrent(map) => map.len(),
+            Self::Custom(store) => store.len(),
+            Self::Singleton(entry) => entry.get().is_some() as usize,
+        }
+    }
+
+    pub(crate) fn iter(&self) -> Box<dyn Iterator<Item = (K, V)> + '_> {
+        match self {
+            Self::Concurrent(map) => Box::new(map.iter()),
+            Self::Custom(store) => store.iter(),
+            Self::Singleton(entry) => Box::new(entry.get().cloned().into_iter()),
+        }
+    }
+
+    /// Like `iter`, but in parallel. A custom store is iterated sequentially
+    /// first, since `NodeStore` only iterates sequentially (and a singleton has
+    /// nothing to parallelize).
+    pub(crate) fn par_iter(&self) -> impl ParallelIterator<Item = (K, V)> + '_
+    where
+        K: Send + Sync,
+        V: Send + Sync,
+    {
+        match self {
+            Self::Concurrent(map) => Either::Left(map.par_iter()),
+            Self::Custom(store) => Either::Right(store.iter().collect::<Vec<_>>().into_par_iter()),
+            Self::Singleton(_) => Either::Right(self.iter().collect::<Vec<_>>().into_par_iter()),
+        }
+    }
+
+    pub(crate) fn retain(&self, mut f: impl FnMut(&K, &mut V) -> bool) {
+        match self {
+            Self::Concurrent(map) => map.retain(f),
+            Self::Custom(store) => store.retain(&mut f),
+            // The entry of a singleton is always kept.
+            Self::Singleton(_) => {}
+        }
+    }
+
+    pub(crate) fn extend(&self, entries: impl IntoIterator<Item = (K, V)>) {
+        match self {
+            Self::Concurrent(map) => map.extend(entries),
+            Self::Custom(store) => store.extend(entries.into_iter().collect()),
+            Self::Singleton(_) => {
+                for (key, value) in entries {
+                    self.get_or_insert_with_key(&key, |_| value);
+                }
+            }
+        }
+    }
+}
+
+impl<K: Debug + Clone + Eq + Hash, V: Debug + Clone> Debug for NodeMap<K, V> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
@@ -0,0 +1,85 @@
+use std::{
+    hash::Hash,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver},
+        Arc,
+    },
+};
+
+use parking_lot::RwLock;
+
+use crate::{Graph, Node};
+
+/// The subscribers to changed results, shared by every iteration incremented
+/// from the same graph.
+pub(crate) type Subscribers<Q, R> = Arc<RwLock<Vec<Subscriber<Q, R>>>>;
+
+/// Sends a change, returning `false` if the receiver was dropped.
+type SendChange<Q, R> = Box<dyn Fn(&Q, &R) -> bool + Send + Sync>;
+
+pub(crate) struct Subscriber<Q, R> {
+    send: SendChange<Q, R>,
+    connected: AtomicBool,
+}
+
+impl<Q: Clone + Eq + Hash + Send + Sync, R: Clone + Send + Sync> Graph<Q, R> {
+    /// Subscribes to the queries whose result changes (compared to the
+    /// previous iteration) in this iteration and every iteration incremented
+    /// from it, for which `filter` returns `true`. Each change is sent along
+    /// with the new result, which makes the graph a reactive data source (e.g.
+    /// for re-rendering exactly the affected parts of a UI).
+    ///
+    /// Changes are sent as soon as the query is resolved (or installed, like
+    /// with `set_input`), so they arrive in the order the queries finish
+    /// resolving (which isn't deterministic when resolving in parallel), and
+    /// only for the queries that are actually queried. The channel is
+    /// unbounded: there's no backpressure, so a slow receiver just buffers
+    /// more changes. Dropping the receiver unsubscribes.
+    pub fn subscribe(&self, filter: impl Fn(&Q) -> bool + Send + Sync + 'static) -> Receiver<(Q, R)>
+    where
+        Q: 'static,
+        R: 'static,
+    {
+        let (sender, receiver) = mpsc::channel();
+
+        self.subscribers.write().push(Subscriber {
+            send: Box::new(move |q, result| {
+                !filter(q) || sender.send((q.clone(), result.clone())).is_ok()
+            }),
+            connected: AtomicBool::new(true),
+        });
+
+        receiver
+    }
+}
+
+impl<Q: Clone + Eq + Hash + Send + Sync, R: Send + Sync> Graph<Q, R> {
+    pub(crate) fn has_subscribers(&self) -> bool {
+        !self.subscribers.read().is_empty()
+    }
+
+    /// Sends the node to the subscribers if its result changed, dropping the
+    /// subscribers that were disconnected.
+    pub(crate) fn notify_changed(&self, q: &Q, node: &Node<Q, R>) {
+        if !node.changed {
+            return;
+        }
+
+        let result = self.load(&node.result);
+        let mut disconnected = false;
+
+        for subscriber in self.subscribers.read().iter() {
+            if subscriber.connected.load(Ordering::Relaxed) && !(subscriber.send)(q, &result) {
+                subscriber.connected.store(false, Ordering::Relaxed);
+                disconnected = true;
+            }
+        }
+
+        if disconnected {
+            self.subscribers
+                .write()
+                .retain(|subscriber| subscriber.connected.load(Ordering::Relaxed));
+        }
+    }
+}
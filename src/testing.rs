@@ -0,0 +1,104 @@
+use std::{fmt::Debug, hash::Hash, sync::Arc};
+
+use crate::Graph;
+
+/// Fuzzes the core invariant of incrementality: the result of a query in an
+/// iteration always equals the result of resolving it from scratch (in a
+/// fresh graph, see `Graph::clone_into_fresh`) with the same resolver.
+///
+/// Every mutation creates the next iteration from the latest one (e.g. with
+/// `increment` or `set_input`). Then a pseudo random subset of the queries is
+/// queried, in a pseudo random order, and the results are compared. The
+/// `seed` picks the subsets and orders, so a failure can be reproduced with
+/// the same seed. The queries are also checked on the initial graph, before
+/// any mutation.
+///
+/// Panics with the first query whose incremental result differs, which
+/// usually means the resolver has an untracked dependency (see
+/// `GraphBuilder::verify`), or early cutoff reused a stale result. Returns the
+/// last iteration otherwise.
+///
+/// ```ignore
+/// let graph = assert_incremental_consistency(
+///     Graph::new(Compiler::new(files)),
+///     &[Query::Typecheck("main".into())],
+///     edits.into_iter().map(|edit| {
+///         move |graph: &Arc<Graph<Query, QueryResult>>| graph.increment(edit.apply())
+///     }),
+///     seed,
+/// );
+/// ```
+pub fn assert_incremental_consistency<Q, R, F>(
+    mut graph: Arc<Graph<Q, R>>,
+    queries: &[Q],
+    mutations: impl IntoIterator<Item = F>,
+    seed: u64,
+) -> Arc<Graph<Q, R>>
+where
+    Q: Debug + Clone + Eq + Hash + Send + Sync,
+    R: Debug + Clone + PartialEq + Send + Sync,
+    F: FnOnce(&Arc<Graph<Q, R>>) -> Arc<Graph<Q, R>>,
+{
+    let mut rng = Rng::new(seed);
+
+    check(&graph, queries, &mut rng, 0, seed);
+
+    for (mutation, mutate) in mutations.into_iter().enumerate() {
+        graph = mutate(&graph);
+        check(&graph, queries, &mut rng, mutation + 1, seed);
+    }
+
+    graph
+}
+
+fn check<Q, R>(graph: &Arc<Graph<Q, R>>, queries: &[Q], rng: &mut Rng, mutations: usize, seed: u64)
+where
+    Q: Debug + Clone + Eq + Hash + Send + Sync,
+    R: Debug + Clone + PartialEq + Send + Sync,
+{
+    let fresh = graph.clone_into_fresh();
+    let mut queries = queries.to_vec();
+
+    // A Fisher-Yates shuffle, so dependencies are resolved in different orders.
+    for i in (1..queries.len()).rev() {
+        queries.swap(i, rng.below(i + 1));
+    }
+
+    // Some of the queries are left out of the iteration, so that the next one
+    // resolves them from scratch instead of validating them.
+    for q in queries {
+        if rng.below(4) == 0 {
+            continue;
+        }
+
+        let incremental = graph.query(q.clone());
+        let from_scratch = fresh.query(q.clone());
+
+        assert_eq!(
+            incremental, from_scratch,
+            "the result of {:?} differs from resolving it from scratch (after {} mutations, with seed {})",
+            q, mutations, seed
+        );
+    }
+}
+
+/// A xorshift64* generator, which is plenty for picking subsets and orders.
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        // Xorshift gets stuck at zero.
+        Self(seed.max(1))
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+}
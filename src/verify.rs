@@ -0,0 +1,88 @@
+use std::{backtrace::Backtrace, fmt::Debug, hash::Hash, sync::Arc};
+
+use hashbrown::HashSet;
+
+use crate::{cycle::Path, Graph, Node, QueryResolver};
+
+/// A report of an impure query: a query whose old result was reused (since
+/// none of its dependencies changed), but that resolved to a different result
+/// (or queried different dependencies) when it was resolved again in
+/// verification mode.
+///
+/// Since none of the tracked dependencies changed, they can't explain the
+/// difference. This almost always means the resolver read some state without
+/// going through `QueryResolver::query` (an untracked dependency), or isn't
+/// deterministic. A different set of dependencies catches an untracked read
+/// even when it didn't end up changing the result.
+#[derive(Debug, Clone)]
+pub struct ImpurityReport {
+    /// The `Debug` output of the query.
+    pub query: String,
+    /// The `Debug` output of the reused old result.
+    pub old: String,
+    /// The `Debug` output of the result it was resolved to again.
+    pub new: String,
+    /// The `Debug` output of the (unchanged) dependencies of the old result.
+    pub old_edges: Vec<String>,
+    /// The `Debug` output of the dependencies queried when it was resolved
+    /// again.
+    pub new_edges: Vec<String>,
+    /// The backtrace of where the mismatch was detected (the validation that
+    /// reused the old result). It's only captured if backtraces are enabled
+    /// (see `Backtrace::capture`).
+    pub backtrace: Arc<Backtrace>,
+}
+
+/// Formats queries and results for impurity reports. It's captured by the
+/// `GraphBuilder` so that only verification requires `Q: Debug, R: Debug`.
+pub(crate) struct Verifier<Q, R> {
+    pub(crate) debug_query: fn(&Q) -> String,
+    pub(crate) debug_result: fn(&R) -> String,
+}
+
+impl<Q: Debug, R: Debug> Verifier<Q, R> {
+    pub(crate) fn new() -> Self {
+        Self {
+            debug_query: |q| format!("{:?}", q),
+            debug_result: |result| format!("{:?}", result),
+        }
+    }
+}
+
+impl<Q: Clone + Eq + Hash + Send + Sync, R: Send + Sync> Graph<Q, R> {
+    /// Returns the impurities found in this iteration so far. This is always
+    /// empty unless the graph was built with `GraphBuilder::verify`.
+    pub fn impurity_reports(&self) -> Vec<ImpurityReport> {
+        self.impurity_reports.lock().clone()
+    }
+
+    /// In verification mode, resolves the query again and reports an impurity
+    /// if the result (or the dependencies) differ from the old node that's
+    /// about to be reused.
+    pub(crate) fn verify(self: &Arc<Self>, q: &Q, path: &Path<Q>, old: &Node<Q, R>) {
+        let Some(verifier) = &self.config.verifier else {
+            return;
+        };
+
+        let resolver = Arc::new(QueryResolver::new(self.clone(), path.clone()));
+        let new = self.store(self.resolver.resolve(q.clone(), resolver.clone()));
+        let (new_edges, _) = resolver.take_edges();
+
+        if self.changed(&old.result, &new) || new_edges != old.edges_from {
+            let debug_query = |q: &Q| self.describe_query(q, verifier.debug_query);
+            let debug_result =
+                |result| self.describe_result(&self.load(result), verifier.debug_result);
+            let debug_edges =
+                |edges: &HashSet<Q>| edges.iter().map(debug_query).collect::<Vec<_>>();
+
+            self.impurity_reports.lock().push(ImpurityReport {
+                query: debug_query(q),
+                old: debug_result(&old.result),
+                new: debug_result(&new),
+                old_edges: debug_edges(&old.edges_from),
+                new_edges: debug_edges(&new_edges),
+                backtrace: Arc::new(Backtrace::capture()),
+            });
+        }
+    }
+}